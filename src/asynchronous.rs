@@ -1,17 +1,36 @@
-use crate::error::Error;
+use crate::error::{Error, TryForEachError};
+use core::future::Future;
 use core::pin::Pin;
-use futures::stream::Stream;
+use futures::stream::{FusedStream, Stream};
 use futures::task::{Context, Poll};
 use pin_project::pin_project;
+use std::collections::VecDeque;
 use std::time::Duration;
-use tokio::time::timeout;
+use tokio::time::{sleep, sleep_until, timeout_at, Instant, Sleep};
 use tokio_stream::StreamExt;
+#[cfg(feature = "tokio-util")]
+use tokio_util::sync::CancellationToken;
 
 #[pin_project]
 pub struct TimeoutStream<R: Stream> {
     #[pin]
     source: R,
-    buffer: Vec<R::Item>,
+    buffer: VecDeque<R::Item>,
+    #[cfg(feature = "tokio-util")]
+    cancellation: Option<CancellationToken>,
+    timer: Option<Pin<Box<Sleep>>>,
+    // Set once the source has ended, after which it is never polled again.
+    done: bool,
+    min_interval: Option<Duration>,
+    // Holds back the next item until `min_interval` has passed since the last one
+    pace: Option<Pin<Box<Sleep>>>,
+    // Deadline shared by every timed wait, from `budget`
+    budget: Option<Instant>,
+    // Pends once after every so many items, from `cooperative`
+    cooperative: Option<usize>,
+    since_yield: usize,
+    // Deepest the buffer has been, once `with_buffer_watermark` is on
+    watermark: Option<usize>,
 }
 
 impl<R: Stream> TimeoutStream<R> {
@@ -19,60 +38,668 @@ impl<R: Stream> TimeoutStream<R> {
      * Use this constructor
      */
     pub async fn with_stream(source: R) -> Result<TimeoutStream<R>, Error> {
-        Ok(TimeoutStream {
+        Ok(TimeoutStream::new(source))
+    }
+
+    fn new(source: R) -> TimeoutStream<R> {
+        TimeoutStream {
             source,
-            buffer: Vec::new(),
-        })
+            buffer: VecDeque::new(),
+            #[cfg(feature = "tokio-util")]
+            cancellation: None,
+            timer: None,
+            done: false,
+            min_interval: None,
+            pace: None,
+            budget: None,
+            cooperative: None,
+            since_yield: 0,
+            watermark: None,
+        }
+    }
+
+    /// Any `next_timeout`/`peek_timeout` in flight when `token` is cancelled
+    /// resolves to `Error::Cancelled`, as does every call made after it.
+    #[cfg(feature = "tokio-util")]
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancellation = Some(token);
+        self
+    }
+
+    /// Spaces out the items this stream yields so that consecutive ones are at least
+    /// `interval` apart, to avoid flooding whatever consumes them. Fast items are delayed,
+    /// never dropped. Peeking is not affected. The spacing runs on tokio's timer, so it is
+    /// not supported under the `wasm` feature on `wasm32`.
+    pub fn min_interval(mut self, interval: Duration) -> Self {
+        self.min_interval = Some(interval);
+        self
+    }
+
+    /// Returns `Pending` (waking itself right away) once after every `every` items, so that
+    /// a source which is always ready, such as an in-memory `iter`, can't starve other tasks
+    /// on a single-threaded executor. Like `tokio::task::yield_now`, only built in. An
+    /// `every` of 0 counts as 1.
+    pub fn cooperative(mut self, every: usize) -> Self {
+        self.cooperative = Some(every.max(1));
+        self
+    }
+
+    /// Starts tracking the most items the buffer has held at once, for sizing bounded
+    /// channels from what peeking and prefetching really need. See `max_buffered`.
+    pub fn with_buffer_watermark(mut self) -> Self {
+        self.watermark = Some(self.buffer.len());
+        self
+    }
+
+    /// The high-water mark of the buffer since `with_buffer_watermark`, or 0 if that's not on.
+    pub fn max_buffered(&self) -> usize {
+        self.watermark.unwrap_or(0)
+    }
+
+    /// Caps all timed waits from now on by one deadline, `total` away, so that a sequence of
+    /// `next_timeout`/`peek_timeout` calls stays within `total` overall rather than each
+    /// getting its full timeout. Lasts until `reset_budget`. Not supported under the `wasm`
+    /// feature on `wasm32`: a budget turns every timeout into a tokio deadline.
+    pub fn budget(mut self, total: Duration) -> Self {
+        self.budget = Some(Instant::now() + total);
+        self
+    }
+
+    /// Lifts the deadline set by `budget`.
+    pub fn reset_budget(self: Pin<&mut Self>) {
+        *self.project().budget = None;
     }
 
     pub async fn peek_timeout(self: Pin<&mut Self>, duration: Duration) -> Result<&R::Item, Error> {
-        // Wrap the future with a `Timeout` set to expire in 10 milliseconds.
-        match timeout(duration, self.peek()).await {
-            Ok(Some(item)) => Ok(item),
-            Ok(None) => Err(Error::Disconnected),
-            Err(_) => Err(Error::TimedOut),
+        let guard = self.guard();
+        match guard.timeout(duration, self.peek()).await? {
+            Some(item) => Ok(item),
+            None => Err(Error::Disconnected),
         }
     }
 
+    /// `peek_timeout` that also tells whether the item was already buffered or had to be
+    /// fetched from the source, for checking that prefetching pays off.
+    pub async fn peek_timeout_detailed(
+        self: Pin<&mut Self>,
+        duration: Duration,
+    ) -> Result<(Peeked, &R::Item), Error> {
+        let peeked = if self.buffer.is_empty() {
+            Peeked::Fetched
+        } else {
+            Peeked::Buffered
+        };
+        Ok((peeked, self.peek_timeout(duration).await?))
+    }
+
+    /// Runs `f` on the next item, which stays unconsumed, and hands back what `f` returns.
+    /// Unlike the reference from `peek_timeout`, the result doesn't keep the stream borrowed.
+    pub async fn with_peeked<U>(
+        self: Pin<&mut Self>,
+        duration: Duration,
+        f: impl FnOnce(&R::Item) -> U,
+    ) -> Result<U, Error> {
+        self.peek_timeout(duration).await.map(f)
+    }
+
     pub async fn next_timeout(
         mut self: Pin<&mut Self>,
         duration: Duration,
     ) -> Result<R::Item, Error> {
-        // Wrap the future with a `Timeout` set to expire in 10 milliseconds.
-        match timeout(duration, self.next()).await {
-            Ok(Some(item)) => Ok(item),
-            Ok(None) => Err(Error::Disconnected),
-            Err(_) => Err(Error::TimedOut),
+        let guard = self.guard();
+        match guard.timeout(duration, self.next()).await? {
+            Some(item) => Ok(item),
+            None => Err(Error::Disconnected),
+        }
+    }
+
+    /// Consumes the next item only if `pred` holds for it, as `Peekable::next_if` does.
+    /// Otherwise the item stays buffered and the result is `Ok(None)`.
+    pub async fn next_if(
+        mut self: Pin<&mut Self>,
+        duration: Duration,
+        pred: impl FnOnce(&R::Item) -> bool,
+    ) -> Result<Option<R::Item>, Error> {
+        if pred(self.as_mut().peek_timeout(duration).await?) {
+            // Already buffered, but going through `next` keeps any pacing
+            Ok(self.next().await)
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `next_if` for the common case of consuming an expected item, such as a frame marker.
+    pub async fn next_if_eq(
+        self: Pin<&mut Self>,
+        duration: Duration,
+        expected: &R::Item,
+    ) -> Result<Option<R::Item>, Error>
+    where
+        R::Item: PartialEq,
+    {
+        self.next_if(duration, |item| item == expected).await
+    }
+
+    /// Like `next_timeout`, but against an absolute deadline. Deadlines are tokio instants,
+    /// so they follow `tokio::time::pause` and `advance` in tests, and there are none to be
+    /// had in the browser: this is not supported under the `wasm` feature on `wasm32`.
+    pub async fn next_timeout_at(
+        mut self: Pin<&mut Self>,
+        deadline: Instant,
+    ) -> Result<R::Item, Error> {
+        let guard = self.guard();
+        match guard.timeout_at(deadline, self.next()).await? {
+            Some(item) => Ok(item),
+            None => Err(Error::Disconnected),
         }
     }
 
     pub async fn peek(mut self: Pin<&mut Self>) -> Option<&R::Item> {
-        if self.as_mut().project().buffer.is_empty() {
-            match self.next().await {
-                Some(item) => self.as_mut().project().buffer.push(item),
-                None => return None,
+        self.as_mut().fill(1).await.ok()?;
+        self.project().buffer.front()
+    }
+
+    /// Poll-based counterpart of `next_timeout` for hand-written combinators.
+    ///
+    /// Resolves to `Err(Error::TimedOut)` once `deadline` has passed without an item. The
+    /// timer is kept inside the stream and re-armed whenever a different deadline is passed,
+    /// so a caller may poll repeatedly with the same deadline and will be woken when it fires.
+    /// Like every deadline-based method, not supported under the `wasm` feature on `wasm32`.
+    ///
+    /// A `budget` that runs out first cuts the wait short as it does for `next_timeout`. A
+    /// cancelled token gives `Err(Error::Cancelled)` from the next poll on, but doesn't wake
+    /// the caller by itself.
+    pub fn poll_next_timeout(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        deadline: Instant,
+    ) -> Poll<Result<R::Item, Error>> {
+        #[cfg(feature = "tokio-util")]
+        if matches!(&self.cancellation, Some(token) if token.is_cancelled()) {
+            *self.project().timer = None;
+            return Poll::Ready(Err(Error::Cancelled));
+        }
+        let deadline = self.budget.map_or(deadline, |budget| budget.min(deadline));
+
+        match self.as_mut().poll_next(cx) {
+            Poll::Ready(Some(item)) => {
+                *self.project().timer = None;
+                return Poll::Ready(Ok(item));
+            }
+            Poll::Ready(None) => {
+                *self.project().timer = None;
+                return Poll::Ready(Err(Error::Disconnected));
+            }
+            Poll::Pending => {}
+        }
+
+        let timer = self.project().timer;
+        let sleep = timer.get_or_insert_with(|| Box::pin(sleep_until(deadline)));
+        if sleep.deadline() != deadline {
+            sleep.as_mut().reset(deadline);
+        }
+
+        match sleep.as_mut().poll(cx) {
+            Poll::Ready(()) => {
+                *timer = None;
+                Poll::Ready(Err(Error::TimedOut))
             }
+            Poll::Pending => Poll::Pending,
+        }
+    }
+
+    /// Waits for the next item, yielding `Err(Error::TimedOut)` every `tick` until it arrives.
+    ///
+    /// The returned stream ends right after the item (or after `Err(Error::Disconnected)`),
+    /// which makes it convenient for driving a progress indicator during a long wait.
+    pub fn ticking_next(self, tick: Duration) -> impl Stream<Item = Result<R::Item, Error>> {
+        futures::stream::unfold(Some(Box::pin(self)), move |state| async move {
+            let mut stream = state?;
+            match stream.as_mut().next_timeout(tick).await {
+                Err(Error::TimedOut) => Some((Err(Error::TimedOut), Some(stream))),
+                result => Some((result, None)),
+            }
+        })
+    }
+
+    /// Turns the stream into one of results, so that `StreamExt` combinators see timeouts
+    /// inline. Every wait for an item is bounded by `duration`: a lapse yields
+    /// `Err(Error::TimedOut)` and the wait starts over, without losing the item. The stream
+    /// ends, without an error, when the source does.
+    pub fn into_timeout_results(
+        self,
+        duration: Duration,
+    ) -> impl Stream<Item = Result<R::Item, Error>> {
+        futures::stream::unfold(Box::pin(self), move |mut stream| async move {
+            match stream.as_mut().next_timeout(duration).await {
+                Err(Error::Disconnected) => None,
+                result => Some((result, stream)),
+            }
+        })
+    }
+
+    /// Buffers until at least `n` items are lined up and returns the first `n` of them, all
+    /// still unconsumed. If the stream ends or `duration` elapses first, whatever was buffered
+    /// stays buffered for later reads, and `Disconnected` or `TimedOut` is returned.
+    pub async fn peek_n_timeout(
+        mut self: Pin<&mut Self>,
+        n: usize,
+        duration: Duration,
+    ) -> Result<&[R::Item], Error> {
+        let guard = self.guard();
+        guard.timeout(duration, self.as_mut().fill(n)).await??;
+        Ok(&self.project().buffer.make_contiguous()[..n])
+    }
+
+    /// Prefetches until at least `depth` items are buffered, waiting at most `duration` in
+    /// total, and returns how many are buffered. A timeout or the end of the stream just stops
+    /// the fill short, keeping whatever was buffered.
+    pub async fn fill_to(
+        mut self: Pin<&mut Self>,
+        depth: usize,
+        duration: Duration,
+    ) -> Result<usize, Error> {
+        let guard = self.guard();
+        match guard.timeout(duration, self.as_mut().fill(depth)).await {
+            Ok(Ok(())) | Ok(Err(Error::Disconnected)) | Err(Error::TimedOut) => {
+                Ok(self.buffer.len())
+            }
+            Ok(Err(e)) | Err(e) => Err(e),
+        }
+    }
+
+    /// Collects items until the stream ends or `total` has elapsed, under one deadline.
+    /// Running out of time is not an error: whatever was collected by then is returned, and
+    /// the rest stays in the stream for later. Only other errors, such as a cancellation,
+    /// are returned as such, with what was collected put back in front of the stream. Being
+    /// built on `next_timeout_at`, it is not supported under the `wasm` feature on `wasm32`.
+    pub async fn collect_timeout(
+        mut self: Pin<&mut Self>,
+        total: Duration,
+    ) -> Result<Vec<R::Item>, Error> {
+        let deadline = Instant::now() + total;
+        let mut items = Vec::new();
+        loop {
+            match self.as_mut().next_timeout_at(deadline).await {
+                Ok(item) => items.push(item),
+                Err(Error::Disconnected) | Err(Error::TimedOut) => return Ok(items),
+                #[cfg(feature = "tokio-util")]
+                Err(e @ Error::Cancelled) => return Err(self.give_back(items, e)),
+                // Only the thread-backed side fails otherwise, but the `Error` is shared
+                #[cfg(feature = "sync")]
+                Err(e) => return Err(self.give_back(items, e)),
+            }
+        }
+    }
+
+    /// Puts `items` back in front of the buffer, in order, before failing with `error`.
+    #[cfg(any(feature = "sync", feature = "tokio-util"))]
+    fn give_back(self: Pin<&mut Self>, items: Vec<R::Item>, error: Error) -> Error {
+        let this = self.project();
+        for item in items.into_iter().rev() {
+            this.buffer.push_front(item);
+        }
+        if let Some(watermark) = this.watermark {
+            *watermark = (*watermark).max(this.buffer.len());
+        }
+        error
+    }
+
+    /// Folds the stream using its first item as the seed, giving `Ok(None)` for an empty
+    /// stream. A gap between items longer than `timeout` abandons the fold with `TimedOut`.
+    pub async fn reduce_timeout(
+        self,
+        timeout: Duration,
+        mut f: impl FnMut(R::Item, R::Item) -> R::Item,
+    ) -> Result<Option<R::Item>, Error> {
+        let mut stream = Box::pin(self);
+
+        let mut accumulator = match stream.as_mut().next_timeout(timeout).await {
+            Ok(item) => item,
+            Err(Error::Disconnected) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+
+        loop {
+            match stream.as_mut().next_timeout(timeout).await {
+                Ok(item) => accumulator = f(accumulator, item),
+                Err(Error::Disconnected) => return Ok(Some(accumulator)),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Feeds every item to `f` until the stream ends, which counts as success. Stops early
+    /// with `TryForEachError::Callback` if `f` fails, or with `TryForEachError::Stream` when
+    /// a gap between items exceeds `timeout`.
+    pub async fn try_for_each_timeout<E>(
+        self,
+        timeout: Duration,
+        mut f: impl FnMut(R::Item) -> Result<(), E>,
+    ) -> Result<(), TryForEachError<E>> {
+        let mut stream = Box::pin(self);
+        loop {
+            match stream.as_mut().next_timeout(timeout).await {
+                Ok(item) => f(item).map_err(TryForEachError::Callback)?,
+                Err(Error::Disconnected) => return Ok(()),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    /// Takes everything that is available without waiting: the buffered items, then whatever
+    /// the source has ready, stopping as soon as it would pend or it ends. The counterpart of
+    /// the sync `try_iter`. Since it never waits, the stream's pacing doesn't apply.
+    pub fn drain_ready(mut self: Pin<&mut Self>) -> Vec<R::Item> {
+        let mut items: Vec<R::Item> = self.as_mut().project().buffer.drain(..).collect();
+
+        // Nothing waits on a wakeup from here, so any waker will do
+        let mut cx = Context::from_waker(futures::task::noop_waker_ref());
+        while let Poll::Ready(Some(item)) = self.as_mut().poll_source(&mut cx) {
+            items.push(item);
+        }
+        items
+    }
+
+    /// Buffers items from the source until there are at least `depth` of them.
+    async fn fill(mut self: Pin<&mut Self>, depth: usize) -> Result<(), Error> {
+        while self.buffer.len() < depth {
+            let item = match futures::future::poll_fn(|cx| self.as_mut().poll_source(cx)).await {
+                Some(item) => item,
+                None => return Err(Error::Disconnected),
+            };
+
+            let this = self.as_mut().project();
+            this.buffer.push_back(item);
+            if let Some(watermark) = this.watermark {
+                *watermark = (*watermark).max(this.buffer.len());
+            }
+        }
+        Ok(())
+    }
+
+    /// Polls the source itself, bypassing the buffer, unless it has already ended.
+    fn poll_source(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<R::Item>> {
+        let this = self.project();
+        if *this.done {
+            return Poll::Ready(None);
+        }
+
+        let polled = this.source.poll_next(cx);
+        if let Poll::Ready(None) = polled {
+            *this.done = true;
+        }
+        polled
+    }
+
+    fn guard(&self) -> Guard {
+        Guard {
+            #[cfg(feature = "tokio-util")]
+            cancellation: self.cancellation.clone(),
+            budget: self.budget,
+        }
+    }
+}
+
+impl<R: Stream + Unpin> TimeoutStream<R> {
+    /// Waits for the next item, without a timeout, like `StreamExt::next` but without
+    /// having to import a `StreamExt`. Pinned streams keep using `StreamExt::next`.
+    ///
+    /// ```
+    /// use futures::stream::iter;
+    /// use timeout_iterator::asynchronous::TimeoutStream;
+    ///
+    /// # #[tokio::main(flavor = "current_thread")]
+    /// # async fn main() {
+    /// let mut stream = TimeoutStream::with_stream(iter(vec![1, 2])).await.unwrap();
+    /// assert_eq!(stream.next().await, Some(1));
+    /// assert_eq!(stream.next().await, Some(2));
+    /// assert_eq!(stream.next().await, None);
+    /// # }
+    /// ```
+    pub async fn next(&mut self) -> Option<R::Item> {
+        futures::future::poll_fn(|cx| Pin::new(&mut *self).poll_next(cx)).await
+    }
+
+    /// `next_timeout` for an `Unpin` stream without pinning it first, after the manner of
+    /// `StreamExt::poll_next_unpin`. Handy for a stream kept in a struct field.
+    pub async fn next_timeout_unpin(&mut self, duration: Duration) -> Result<R::Item, Error> {
+        Pin::new(self).next_timeout(duration).await
+    }
+
+    /// `peek_timeout` for an `Unpin` stream without pinning it first.
+    pub async fn peek_timeout_unpin(&mut self, duration: Duration) -> Result<&R::Item, Error> {
+        Pin::new(self).peek_timeout(duration).await
+    }
+
+    /// Owning variant of `next_timeout` that hands the stream back alongside the result.
+    ///
+    /// The returned future borrows nothing, so it is `'static` whenever `R` is and can be
+    /// moved into `tokio::spawn`.
+    pub async fn into_next_timeout(mut self, duration: Duration) -> (Self, Result<R::Item, Error>) {
+        let result = Pin::new(&mut self).next_timeout(duration).await;
+        (self, result)
+    }
+}
+
+/// Wraps a tokio channel, the usual input of actor-style code, as a `TimeoutStream`. The
+/// stream ends once every sender is dropped.
+pub fn from_tokio_receiver<T>(
+    receiver: tokio::sync::mpsc::Receiver<T>,
+) -> TimeoutStream<tokio_stream::wrappers::ReceiverStream<T>> {
+    TimeoutStream::new(tokio_stream::wrappers::ReceiverStream::new(receiver))
+}
+
+/// Where the item from `TimeoutStream::peek_timeout_detailed` came from.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Peeked {
+    /// It was already buffered.
+    Buffered,
+    /// It had to be fetched from the source.
+    Fetched,
+}
+
+/// Everything that can cut a wait short, detached from the stream so that it
+/// can be held while the stream itself is mutably borrowed by the wait.
+struct Guard {
+    #[cfg(feature = "tokio-util")]
+    cancellation: Option<CancellationToken>,
+    budget: Option<Instant>,
+}
+
+impl Guard {
+    async fn timeout<F: Future>(self, duration: Duration, future: F) -> Result<F::Output, Error> {
+        match self.budget {
+            Some(_) => self.timeout_at(Instant::now() + duration, future).await,
+            None => self.cancellable(timer::timeout(duration, future)).await,
+        }
+    }
+
+    async fn timeout_at<F: Future>(self, deadline: Instant, future: F) -> Result<F::Output, Error> {
+        let deadline = self.budget.map_or(deadline, |budget| budget.min(deadline));
+        self.cancellable(async {
+            // A timed-out future is dropped, never consuming the item it was waiting on.
+            timeout_at(deadline, future)
+                .await
+                .map_err(|_| Error::TimedOut)
+        })
+        .await
+    }
+
+    async fn cancellable<F, T>(self, timed: F) -> Result<T, Error>
+    where
+        F: Future<Output = Result<T, Error>>,
+    {
+        #[cfg(feature = "tokio-util")]
+        if let Some(token) = self.cancellation {
+            return match token.run_until_cancelled(timed).await {
+                Some(result) => result,
+                None => Err(Error::Cancelled),
+            };
+        }
+
+        timed.await
+    }
+}
+
+/// The timer behind the relative timeouts (`next_timeout`, `peek_timeout` and friends).
+/// Browsers have no tokio timer, so under the `wasm` feature on `wasm32` these run on
+/// `gloo-timers` instead. Deadline-based APIs still take tokio instants, so `budget`,
+/// `min_interval`, `next_timeout_at`, `poll_next_timeout` and `collect_timeout` are not
+/// supported there.
+mod timer {
+    use crate::error::Error;
+    use core::future::Future;
+    use std::time::Duration;
+
+    #[cfg(not(all(feature = "wasm", target_arch = "wasm32")))]
+    pub(super) async fn timeout<F: Future>(
+        duration: Duration,
+        future: F,
+    ) -> Result<F::Output, Error> {
+        tokio::time::timeout(duration, future)
+            .await
+            .map_err(|_| Error::TimedOut)
+    }
+
+    #[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+    pub(super) async fn timeout<F: Future>(
+        duration: Duration,
+        future: F,
+    ) -> Result<F::Output, Error> {
+        use futures::future::{select, Either};
+
+        futures::pin_mut!(future);
+        match select(future, gloo_timers::future::sleep(duration)).await {
+            Either::Left((output, _)) => Ok(output),
+            Either::Right(_) => Err(Error::TimedOut),
         }
-        self.project().buffer.first()
     }
 }
 
 impl<R: Stream> Stream for TimeoutStream<R> {
     type Item = R::Item;
 
+    fn poll_next(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+    ) -> Poll<Option<<Self as Stream>::Item>> {
+        let this = self.as_mut().project();
+        if let Some(every) = *this.cooperative {
+            if *this.since_yield >= every {
+                *this.since_yield = 0;
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+        }
+        if let Some(pace) = this.pace {
+            futures::ready!(pace.as_mut().poll(cx));
+            *this.pace = None;
+        }
+
+        let polled = match self.as_mut().project().buffer.pop_front() {
+            Some(item) => Poll::Ready(Some(item)),
+            None => self.as_mut().poll_source(cx),
+        };
+
+        if let Poll::Ready(Some(_)) = polled {
+            *self.as_mut().project().since_yield += 1;
+        }
+        if let (Poll::Ready(Some(_)), Some(interval)) = (&polled, self.min_interval) {
+            *self.project().pace = Some(Box::pin(sleep(interval)));
+        }
+        polled
+    }
+}
+
+/// Clones the source along with anything already buffered. The clones then consume
+/// independently, each from its own copy of the source. An in-flight poll timer is not
+/// carried over.
+impl<R> Clone for TimeoutStream<R>
+where
+    R: Stream + Clone,
+    R::Item: Clone,
+{
+    fn clone(&self) -> Self {
+        TimeoutStream {
+            source: self.source.clone(),
+            buffer: self.buffer.clone(),
+            #[cfg(feature = "tokio-util")]
+            cancellation: self.cancellation.clone(),
+            timer: None,
+            done: self.done,
+            min_interval: self.min_interval,
+            pace: self
+                .pace
+                .as_ref()
+                .map(|pace| Box::pin(sleep_until(pace.deadline()))),
+            budget: self.budget,
+            cooperative: self.cooperative,
+            since_yield: self.since_yield,
+            watermark: self.watermark,
+        }
+    }
+}
+
+impl<R: Stream> FusedStream for TimeoutStream<R> {
+    fn is_terminated(&self) -> bool {
+        self.done && self.buffer.is_empty()
+    }
+}
+
+/// Merges several `TimeoutStream`s into a single `TimeoutStream` that yields
+/// each item alongside the index of the stream it came from.
+///
+/// Streams are polled round-robin, starting after the one that last produced an
+/// item, so a fast stream cannot starve the others. Once every stream has ended
+/// the merged stream ends too, which `next_timeout` reports as `Disconnected`.
+pub fn select_all_timeout<S: Stream>(
+    streams: Vec<TimeoutStream<S>>,
+) -> TimeoutStream<SelectAll<S>> {
+    TimeoutStream::new(SelectAll {
+        streams: streams.into_iter().map(|s| Some(Box::pin(s))).collect(),
+        next_index: 0,
+    })
+}
+
+pub struct SelectAll<S: Stream> {
+    streams: Vec<Option<Pin<Box<TimeoutStream<S>>>>>,
+    next_index: usize,
+}
+
+impl<S: Stream> Stream for SelectAll<S> {
+    type Item = (usize, S::Item);
+
     fn poll_next(
         self: Pin<&mut Self>,
         cx: &mut Context<'_>,
     ) -> Poll<Option<<Self as Stream>::Item>> {
-        if !self.buffer.is_empty() {
-            return Poll::Ready(Some(self.project().buffer.remove(0)));
+        let this = self.get_mut();
+        let count = this.streams.len();
+
+        for offset in 0..count {
+            let index = (this.next_index + offset) % count;
+            if let Some(stream) = this.streams[index].as_mut() {
+                match stream.as_mut().poll_next(cx) {
+                    Poll::Ready(Some(item)) => {
+                        this.next_index = (index + 1) % count;
+                        return Poll::Ready(Some((index, item)));
+                    }
+                    Poll::Ready(None) => this.streams[index] = None,
+                    Poll::Pending => {}
+                }
+            }
         }
 
-        self.project().source.poll_next(cx)
+        if this.streams.iter().all(Option::is_none) {
+            Poll::Ready(None)
+        } else {
+            Poll::Pending
+        }
     }
 }
 
-#[cfg(all(test, feature = "async"))]
+#[cfg(all(test, feature = "async", not(target_arch = "wasm32")))]
 mod tests {
     use super::*;
     use assert_matches::assert_matches;
@@ -336,4 +963,778 @@ mod tests {
         let timeout_result = ti.as_mut().next_timeout(Duration::from_secs(1)).await;
         assert!(timeout_result.is_err());
     }
+
+    #[tokio::test]
+    async fn select_all_interleaves_and_disconnects() {
+        let speeds = [10, 20, 30];
+        let streams = speeds
+            .iter()
+            .map(|ms| iter(vec![1u32, 2, 3].into_iter()).throttle(Duration::from_millis(*ms)))
+            .map(TimeoutStream::new)
+            .collect();
+
+        let mut ti = Box::pin(select_all_timeout(streams));
+
+        // Everyone is ready at the start, so the first round visits every stream.
+        let mut first_round = Vec::new();
+        for _ in 0..3 {
+            let (index, item) = ti
+                .as_mut()
+                .next_timeout(Duration::from_secs(1))
+                .await
+                .unwrap();
+            assert_eq!(item, 1);
+            first_round.push(index);
+        }
+        first_round.sort_unstable();
+        assert_eq!(first_round, vec![0, 1, 2]);
+
+        let mut received = vec![vec![1], vec![1], vec![1]];
+        loop {
+            match ti.as_mut().next_timeout(Duration::from_secs(1)).await {
+                Ok((index, item)) => received[index].push(item),
+                Err(e) => {
+                    assert_matches!(e, Error::Disconnected);
+                    break;
+                }
+            }
+        }
+        assert_eq!(received, vec![vec![1, 2, 3], vec![1, 2, 3], vec![1, 2, 3]]);
+    }
+
+    #[tokio::test]
+    async fn select_all_times_out_when_silent() {
+        let streams = (0..3)
+            .map(|_| TimeoutStream::new(futures::stream::pending::<u32>()))
+            .collect();
+
+        let mut ti = Box::pin(select_all_timeout(streams));
+        assert_matches!(
+            ti.as_mut()
+                .next_timeout(Duration::from_millis(100))
+                .await
+                .unwrap_err(),
+            Error::TimedOut
+        );
+    }
+
+    #[cfg(feature = "tokio-util")]
+    #[tokio::test]
+    async fn cancellation_interrupts_next_timeout() {
+        let token = CancellationToken::new();
+        let ti =
+            TimeoutStream::new(futures::stream::pending::<u32>()).with_cancellation(token.clone());
+
+        let waiter = tokio::spawn(async move {
+            let mut ti = Box::pin(ti);
+            let started = std::time::Instant::now();
+            let result = ti.as_mut().next_timeout(Duration::from_secs(10)).await;
+            (result, started.elapsed())
+        });
+
+        tokio::time::sleep(Duration::from_millis(50)).await;
+        token.cancel();
+
+        let (result, elapsed) = waiter.await.unwrap();
+        assert_matches!(result.unwrap_err(), Error::Cancelled);
+        assert!(elapsed < Duration::from_secs(1));
+    }
+
+    #[cfg(feature = "tokio-util")]
+    #[tokio::test(start_paused = true)]
+    async fn cancellation_interrupts_poll_next_timeout() {
+        let token = CancellationToken::new();
+        let mut ti = Box::pin(
+            TimeoutStream::new(futures::stream::pending::<u32>()).with_cancellation(token.clone()),
+        );
+        let deadline = Instant::now() + Duration::from_secs(10);
+
+        assert_matches!(
+            futures::poll!(futures::future::poll_fn(|cx| ti
+                .as_mut()
+                .poll_next_timeout(cx, deadline))),
+            Poll::Pending
+        );
+        token.cancel();
+        assert_matches!(
+            futures::poll!(futures::future::poll_fn(|cx| ti
+                .as_mut()
+                .poll_next_timeout(cx, deadline))),
+            Poll::Ready(Err(Error::Cancelled))
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_bounds_poll_next_timeout() {
+        let mut ti = Box::pin(
+            TimeoutStream::new(futures::stream::pending::<u32>()).budget(Duration::from_secs(1)),
+        );
+        let started = Instant::now();
+        let deadline = started + Duration::from_secs(10);
+
+        let result =
+            futures::future::poll_fn(|cx| ti.as_mut().poll_next_timeout(cx, deadline)).await;
+        assert_matches!(result, Err(Error::TimedOut));
+        assert_eq!(Instant::now() - started, Duration::from_secs(1));
+    }
+
+    #[tokio::test]
+    async fn poll_next_timeout_manually() {
+        let mut ti = Box::pin(TimeoutStream::new(iter(vec![1u32].into_iter())));
+        let deadline = Instant::now() + Duration::from_millis(50);
+
+        assert_matches!(
+            futures::poll!(futures::future::poll_fn(|cx| ti
+                .as_mut()
+                .poll_next_timeout(cx, deadline))),
+            Poll::Ready(Ok(1))
+        );
+        assert_matches!(
+            futures::poll!(futures::future::poll_fn(|cx| ti
+                .as_mut()
+                .poll_next_timeout(cx, deadline))),
+            Poll::Ready(Err(Error::Disconnected))
+        );
+
+        let mut silent = Box::pin(TimeoutStream::new(futures::stream::pending::<u32>()));
+        assert_matches!(
+            futures::poll!(futures::future::poll_fn(|cx| silent
+                .as_mut()
+                .poll_next_timeout(cx, deadline))),
+            Poll::Pending
+        );
+
+        // Awaiting relies on the timer's waker being registered
+        let result =
+            futures::future::poll_fn(|cx| silent.as_mut().poll_next_timeout(cx, deadline)).await;
+        assert_matches!(result, Err(Error::TimedOut));
+        assert!(Instant::now() >= deadline);
+    }
+
+    #[tokio::test]
+    async fn into_next_timeout_spawns() {
+        let ti = TimeoutStream::new(iter(vec![1u32, 2].into_iter()));
+
+        let (ti, result) = tokio::spawn(ti.into_next_timeout(Duration::from_secs(1)))
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap(), 1);
+
+        let (ti, result) = tokio::spawn(ti.into_next_timeout(Duration::from_secs(1)))
+            .await
+            .unwrap();
+        assert_eq!(result.unwrap(), 2);
+
+        let (_, result) = tokio::spawn(ti.into_next_timeout(Duration::from_secs(1)))
+            .await
+            .unwrap();
+        assert_matches!(result.unwrap_err(), Error::Disconnected);
+    }
+
+    #[tokio::test]
+    async fn ticking_next_ticks_until_item() {
+        let throttled_numbers =
+            Box::pin(iter(vec![1u32, 2].into_iter()).throttle(Duration::from_millis(300)));
+        let mut ti = TimeoutStream::new(throttled_numbers);
+
+        assert_eq!(ti.next().await.unwrap(), 1);
+
+        let results: Vec<Result<u32, Error>> =
+            ti.ticking_next(Duration::from_millis(50)).collect().await;
+        let (last, ticks) = results.split_last().unwrap();
+
+        assert!(ticks.len() >= 3);
+        for tick in ticks {
+            assert_matches!(tick, Err(Error::TimedOut));
+        }
+        assert_matches!(last, Ok(2));
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn next_timeout_at_follows_paused_time() {
+        let mut ti = Box::pin(TimeoutStream::new(futures::stream::pending::<u32>()));
+        let deadline = Instant::now() + Duration::from_secs(60);
+
+        // Paused time jumps straight to the deadline once nothing else can make progress
+        assert_matches!(
+            ti.as_mut().next_timeout_at(deadline).await.unwrap_err(),
+            Error::TimedOut
+        );
+        assert!(Instant::now() >= deadline);
+
+        let mut ti = Box::pin(TimeoutStream::new(iter(vec![1u32].into_iter())));
+        let deadline = Instant::now() + Duration::from_secs(60);
+        assert_eq!(ti.as_mut().next_timeout_at(deadline).await.unwrap(), 1);
+        assert_matches!(
+            ti.as_mut().next_timeout_at(deadline).await.unwrap_err(),
+            Error::Disconnected
+        );
+    }
+    /// Panics if polled again after it has ended.
+    struct PanicsAfterEnd {
+        remaining: Vec<u32>,
+        ended: bool,
+    }
+
+    impl Stream for PanicsAfterEnd {
+        type Item = u32;
+
+        fn poll_next(mut self: Pin<&mut Self>, _: &mut Context<'_>) -> Poll<Option<u32>> {
+            assert!(!self.ended, "polled after completion");
+            if self.remaining.is_empty() {
+                self.ended = true;
+                return Poll::Ready(None);
+            }
+            Poll::Ready(Some(self.remaining.remove(0)))
+        }
+    }
+
+    #[tokio::test]
+    async fn source_not_polled_after_end() {
+        let source = PanicsAfterEnd {
+            remaining: vec![1, 2],
+            ended: false,
+        };
+        let mut ti = Box::pin(TimeoutStream::new(source));
+
+        assert_eq!(ti.as_mut().next().await.unwrap(), 1);
+        assert_eq!(ti.as_mut().next().await.unwrap(), 2);
+        assert!(!ti.is_terminated());
+        assert!(ti.as_mut().next().await.is_none());
+        assert!(ti.is_terminated());
+
+        assert!(ti.as_mut().next().await.is_none());
+        assert!(ti.as_mut().peek().await.is_none());
+        assert_matches!(
+            ti.as_mut()
+                .next_timeout(Duration::from_secs(1))
+                .await
+                .unwrap_err(),
+            Error::Disconnected
+        );
+        assert_matches!(
+            ti.as_mut()
+                .peek_timeout(Duration::from_secs(1))
+                .await
+                .unwrap_err(),
+            Error::Disconnected
+        );
+    }
+
+    #[tokio::test]
+    async fn peek_n_timeout_fills() {
+        let mut ti = Box::pin(TimeoutStream::new(iter(vec![1u32, 2, 3, 4].into_iter())));
+
+        assert_eq!(
+            ti.as_mut()
+                .peek_n_timeout(3, Duration::from_secs(1))
+                .await
+                .unwrap(),
+            &[1, 2, 3]
+        );
+        assert_eq!(ti.as_mut().next().await.unwrap(), 1);
+        assert_eq!(
+            ti.as_mut()
+                .peek_n_timeout(3, Duration::from_secs(1))
+                .await
+                .unwrap(),
+            &[2, 3, 4]
+        );
+    }
+
+    #[tokio::test]
+    async fn peek_n_timeout_short_stream() {
+        let mut ti = Box::pin(TimeoutStream::new(iter(vec![1u32, 2].into_iter())));
+
+        assert_matches!(
+            ti.as_mut()
+                .peek_n_timeout(3, Duration::from_secs(1))
+                .await
+                .unwrap_err(),
+            Error::Disconnected
+        );
+        // The prefix is still there
+        assert_eq!(ti.as_mut().next().await.unwrap(), 1);
+        assert_eq!(ti.as_mut().next().await.unwrap(), 2);
+        assert!(ti.as_mut().next().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn peek_n_timeout_times_out() {
+        let throttled =
+            Box::pin(iter(vec![1u32, 2, 3].into_iter()).throttle(Duration::from_secs(10)));
+        let mut ti = Box::pin(TimeoutStream::new(throttled));
+
+        assert_matches!(
+            ti.as_mut()
+                .peek_n_timeout(2, Duration::from_secs(1))
+                .await
+                .unwrap_err(),
+            Error::TimedOut
+        );
+        assert_eq!(ti.as_mut().next().await.unwrap(), 1);
+        assert_eq!(ti.as_mut().next().await.unwrap(), 2);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reduce_timeout_sums() {
+        let throttled = iter(vec![1u32, 2, 3, 4].into_iter()).throttle(Duration::from_millis(100));
+        let ti = TimeoutStream::new(throttled);
+
+        let sum = ti
+            .reduce_timeout(Duration::from_secs(1), |a, b| a + b)
+            .await
+            .unwrap();
+        assert_eq!(sum, Some(10));
+
+        let empty = TimeoutStream::new(iter(Vec::<u32>::new().into_iter()));
+        assert_eq!(
+            empty
+                .reduce_timeout(Duration::from_secs(1), |a, b| a + b)
+                .await
+                .unwrap(),
+            None
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn reduce_timeout_times_out() {
+        let throttled = iter(vec![1u32, 2, 3, 4].into_iter()).throttle(Duration::from_secs(10));
+        let ti = TimeoutStream::new(throttled);
+
+        assert_matches!(
+            ti.reduce_timeout(Duration::from_secs(1), |a, b| a + b)
+                .await
+                .unwrap_err(),
+            Error::TimedOut
+        );
+    }
+
+    #[tokio::test]
+    async fn try_for_each_timeout_happy_path() {
+        let ti = TimeoutStream::new(iter(vec![1u32, 2, 3].into_iter()));
+
+        let mut seen = Vec::new();
+        ti.try_for_each_timeout(Duration::from_secs(1), |item| {
+            seen.push(item);
+            Ok::<(), String>(())
+        })
+        .await
+        .unwrap();
+        assert_eq!(seen, vec![1, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn try_for_each_timeout_callback_error() {
+        let ti = TimeoutStream::new(iter(vec![1u32, 2, 3].into_iter()));
+
+        let mut seen = Vec::new();
+        let result = ti
+            .try_for_each_timeout(Duration::from_secs(1), |item| {
+                if item == 2 {
+                    return Err("two");
+                }
+                seen.push(item);
+                Ok(())
+            })
+            .await;
+        assert_matches!(result.unwrap_err(), TryForEachError::Callback("two"));
+        assert_eq!(seen, vec![1]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn try_for_each_timeout_times_out() {
+        let throttled = iter(vec![1u32, 2].into_iter()).throttle(Duration::from_secs(10));
+        let ti = TimeoutStream::new(throttled);
+
+        let result = ti
+            .try_for_each_timeout(Duration::from_secs(1), |_| Ok::<(), String>(()))
+            .await;
+        assert_matches!(
+            result.unwrap_err(),
+            TryForEachError::Stream(Error::TimedOut)
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn min_interval_spaces_items() {
+        let interval = Duration::from_millis(100);
+        let mut ti = Box::pin(TimeoutStream::new(iter(0u32..5)).min_interval(interval));
+
+        let mut yielded_at = Vec::new();
+        while let Some(item) = ti.as_mut().next().await {
+            yielded_at.push((item, Instant::now()));
+        }
+
+        assert_eq!(
+            yielded_at
+                .iter()
+                .map(|(item, _)| *item)
+                .collect::<Vec<u32>>(),
+            vec![0, 1, 2, 3, 4]
+        );
+        for pair in yielded_at.windows(2) {
+            assert!(pair[1].1 - pair[0].1 >= interval);
+        }
+    }
+
+    #[tokio::test]
+    async fn clones_consume_independently() {
+        let mut original = TimeoutStream::new(iter(vec![1u32, 2, 3].into_iter()));
+        assert_eq!(*Pin::new(&mut original).peek().await.unwrap(), 1);
+
+        let copy = original.clone();
+        assert_eq!(original.collect::<Vec<u32>>().await, vec![1, 2, 3]);
+        assert_eq!(copy.collect::<Vec<u32>>().await, vec![1, 2, 3]);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn blocking_peek_after_timed_out_peek() {
+        let throttled =
+            Box::pin(iter(vec![1u32, 2].into_iter()).throttle(Duration::from_millis(200)));
+        let mut ti = Box::pin(TimeoutStream::new(throttled));
+        assert_eq!(ti.as_mut().next().await.unwrap(), 1);
+
+        assert_matches!(
+            ti.as_mut()
+                .peek_timeout(Duration::from_millis(50))
+                .await
+                .unwrap_err(),
+            Error::TimedOut
+        );
+        assert_eq!(*ti.as_mut().peek().await.unwrap(), 2);
+        assert_eq!(
+            *ti.as_mut()
+                .peek_timeout(Duration::from_millis(50))
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(ti.as_mut().next().await.unwrap(), 2);
+        assert!(ti.as_mut().peek().await.is_none());
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn fill_to_prefetches() {
+        let throttled =
+            Box::pin(iter(vec![1u32, 2, 3].into_iter()).throttle(Duration::from_secs(1)));
+        let mut ti = Box::pin(TimeoutStream::new(throttled));
+
+        assert_eq!(
+            ti.as_mut()
+                .fill_to(2, Duration::from_millis(1500))
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(ti.buffer, [1, 2]);
+
+        // Not enough time for another item: the fill falls short but keeps what it has
+        assert_eq!(
+            ti.as_mut()
+                .fill_to(3, Duration::from_millis(500))
+                .await
+                .unwrap(),
+            2
+        );
+        assert_eq!(ti.as_mut().next().await.unwrap(), 1);
+    }
+
+    #[tokio::test]
+    async fn unpin_streams_need_no_pinning() {
+        struct Parser {
+            lines: TimeoutStream<futures::stream::Iter<std::vec::IntoIter<u32>>>,
+        }
+
+        let mut parser = Parser {
+            lines: TimeoutStream::new(iter(vec![1u32, 2])),
+        };
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(*parser.lines.peek_timeout_unpin(timeout).await.unwrap(), 1);
+        assert_eq!(parser.lines.next_timeout_unpin(timeout).await.unwrap(), 1);
+        assert_eq!(parser.lines.next_timeout_unpin(timeout).await.unwrap(), 2);
+        assert_matches!(
+            parser.lines.next_timeout_unpin(timeout).await.unwrap_err(),
+            Error::Disconnected
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn into_timeout_results_mixes_items_and_timeouts() {
+        let throttled = iter(vec![1u32, 2].into_iter()).throttle(Duration::from_millis(250));
+        let results: Vec<Result<u32, Error>> = TimeoutStream::new(throttled)
+            .into_timeout_results(Duration::from_millis(100))
+            .collect()
+            .await;
+
+        let summary: Vec<Option<u32>> = results.into_iter().map(Result::ok).collect();
+        // The throttle holds back the end of the stream as well
+        assert_eq!(summary, vec![Some(1), None, None, Some(2), None, None]);
+    }
+
+    #[tokio::test]
+    async fn with_peeked_returns_owned_result() {
+        struct Record {
+            id: u32,
+            body: String,
+        }
+
+        let records = vec![Record {
+            id: 7,
+            body: "first".to_owned(),
+        }];
+        let mut ti = Box::pin(TimeoutStream::new(iter(records)));
+
+        let id = ti
+            .as_mut()
+            .with_peeked(Duration::from_secs(1), |record| record.id)
+            .await
+            .unwrap();
+        // The stream is free again while `id` is still around
+        let record = ti.as_mut().next().await.unwrap();
+        assert_eq!((id, record.id, record.body.as_str()), (7, 7, "first"));
+    }
+
+    #[tokio::test]
+    async fn next_if_consumes_only_matches() {
+        let mut ti = Box::pin(TimeoutStream::new(iter(vec![1u32, 2, 3])));
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(
+            ti.as_mut().next_if(timeout, |n| *n == 1).await.unwrap(),
+            Some(1)
+        );
+        assert_eq!(
+            ti.as_mut().next_if(timeout, |n| *n == 1).await.unwrap(),
+            None
+        );
+        assert_eq!(ti.as_mut().next_if_eq(timeout, &2).await.unwrap(), Some(2));
+        assert_eq!(ti.as_mut().next_if_eq(timeout, &2).await.unwrap(), None);
+        assert_eq!(ti.as_mut().next().await.unwrap(), 3);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn next_if_times_out() {
+        let mut ti = Box::pin(TimeoutStream::new(futures::stream::pending::<u32>()));
+        assert_matches!(
+            ti.as_mut()
+                .next_if(Duration::from_secs(1), |_| true)
+                .await
+                .unwrap_err(),
+            Error::TimedOut
+        );
+        assert_matches!(
+            ti.as_mut()
+                .next_if_eq(Duration::from_secs(1), &1)
+                .await
+                .unwrap_err(),
+            Error::TimedOut
+        );
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn budget_bounds_consecutive_waits() {
+        let throttled = iter(vec![1u32, 2, 3].into_iter()).throttle(Duration::from_secs(2));
+        let mut ti = Box::pin(TimeoutStream::new(throttled).budget(Duration::from_secs(3)));
+        let started = Instant::now();
+
+        assert_eq!(
+            ti.as_mut()
+                .next_timeout(Duration::from_secs(5))
+                .await
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            ti.as_mut()
+                .next_timeout(Duration::from_secs(5))
+                .await
+                .unwrap(),
+            2
+        );
+        assert_matches!(
+            ti.as_mut()
+                .peek_timeout(Duration::from_secs(5))
+                .await
+                .unwrap_err(),
+            Error::TimedOut
+        );
+        assert_eq!(started.elapsed(), Duration::from_secs(3));
+
+        ti.as_mut().reset_budget();
+        assert_eq!(
+            ti.as_mut()
+                .next_timeout(Duration::from_secs(5))
+                .await
+                .unwrap(),
+            3
+        );
+    }
+
+    #[tokio::test]
+    async fn drain_ready_takes_everything_available() {
+        let mut ti = Box::pin(TimeoutStream::new(iter(vec![1u32, 2, 3, 4])));
+        assert_eq!(*ti.as_mut().peek().await.unwrap(), 1);
+
+        assert_eq!(ti.as_mut().drain_ready(), vec![1, 2, 3, 4]);
+        assert!(ti.as_mut().drain_ready().is_empty());
+        assert!(ti.as_mut().next().await.is_none());
+
+        let mut quiet = Box::pin(TimeoutStream::new(
+            iter(vec![1u32]).chain(futures::stream::pending()),
+        ));
+        assert_eq!(quiet.as_mut().drain_ready(), vec![1]);
+    }
+
+    #[tokio::test]
+    async fn peek_timeout_detailed_tells_buffered_from_fetched() {
+        let mut ti = Box::pin(TimeoutStream::new(iter(vec![1u32, 2])));
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(
+            ti.as_mut().peek_timeout_detailed(timeout).await.unwrap(),
+            (Peeked::Fetched, &1)
+        );
+        assert_eq!(
+            ti.as_mut().peek_timeout_detailed(timeout).await.unwrap(),
+            (Peeked::Buffered, &1)
+        );
+    }
+
+    #[tokio::test(flavor = "current_thread")]
+    async fn cooperative_lets_other_tasks_run() {
+        use std::sync::atomic::{AtomicUsize, Ordering};
+        use std::sync::Arc;
+
+        let progress = Arc::new(AtomicUsize::new(0));
+        let ticker = {
+            let progress = progress.clone();
+            tokio::spawn(async move {
+                loop {
+                    progress.fetch_add(1, Ordering::SeqCst);
+                    tokio::task::yield_now().await;
+                }
+            })
+        };
+
+        let mut ti = Box::pin(
+            TimeoutStream::with_stream(iter(0..10_000))
+                .await
+                .unwrap()
+                .cooperative(100),
+        );
+        let mut drained = 0;
+        while ti.as_mut().next().await.is_some() {
+            drained += 1;
+        }
+        ticker.abort();
+
+        assert_eq!(drained, 10_000);
+        assert!(progress.load(Ordering::SeqCst) >= 10);
+    }
+
+    #[tokio::test]
+    async fn buffer_watermark() {
+        let mut ti = Box::pin(
+            TimeoutStream::with_stream(iter(0..10))
+                .await
+                .unwrap()
+                .with_buffer_watermark(),
+        );
+        assert_eq!(ti.max_buffered(), 0);
+
+        let timeout = Duration::from_secs(1);
+        ti.as_mut().peek_n_timeout(2, timeout).await.unwrap();
+        ti.as_mut().peek_n_timeout(4, timeout).await.unwrap();
+        assert_eq!(ti.as_mut().next().await, Some(0));
+        ti.as_mut().peek_n_timeout(3, timeout).await.unwrap();
+        assert_eq!(ti.max_buffered(), 4);
+    }
+
+    #[tokio::test(start_paused = true)]
+    async fn collect_timeout_within_budget() {
+        let throttled = iter(0u32..10).throttle(Duration::from_millis(100));
+        let mut ti = Box::pin(TimeoutStream::with_stream(throttled).await.unwrap());
+
+        let collected = ti
+            .as_mut()
+            .collect_timeout(Duration::from_millis(350))
+            .await
+            .unwrap();
+        assert_eq!(collected, vec![0, 1, 2, 3]);
+
+        let rest = ti
+            .as_mut()
+            .collect_timeout(Duration::from_secs(1))
+            .await
+            .unwrap();
+        assert_eq!(rest, (4..10).collect::<Vec<_>>());
+    }
+
+    #[cfg(feature = "tokio-util")]
+    #[tokio::test(start_paused = true)]
+    async fn cancelled_collect_timeout_keeps_items() {
+        let token = CancellationToken::new();
+        let throttled = iter(0u32..10).throttle(Duration::from_millis(100));
+        let mut ti = Box::pin(TimeoutStream::new(throttled).with_cancellation(token.clone()));
+
+        let canceller = tokio::spawn(async move {
+            sleep(Duration::from_millis(250)).await;
+            token.cancel();
+        });
+        assert_matches!(
+            ti.as_mut().collect_timeout(Duration::from_secs(5)).await,
+            Err(Error::Cancelled)
+        );
+        canceller.await.unwrap();
+
+        // The three collected before the cancellation come first, then the rest
+        assert_eq!(ti.buffer.len(), 3);
+        let everything: Vec<u32> = ti.as_mut().collect().await;
+        assert_eq!(everything, (0..10).collect::<Vec<_>>());
+    }
+
+    #[tokio::test]
+    async fn wraps_tokio_receiver() {
+        let (sink, source) = tokio::sync::mpsc::channel(4);
+        let mut ti = Box::pin(from_tokio_receiver(source));
+        let timeout = Duration::from_millis(50);
+
+        sink.send(1u32).await.unwrap();
+        assert_eq!(ti.as_mut().next_timeout(timeout).await.unwrap(), 1);
+        assert_matches!(
+            ti.as_mut().next_timeout(timeout).await,
+            Err(Error::TimedOut)
+        );
+
+        sink.send(2).await.unwrap();
+        drop(sink);
+        assert_eq!(ti.as_mut().next_timeout(timeout).await.unwrap(), 2);
+        assert_matches!(
+            ti.as_mut().next_timeout(timeout).await,
+            Err(Error::Disconnected)
+        );
+    }
+}
+
+#[cfg(all(test, feature = "wasm", target_arch = "wasm32"))]
+mod wasm_tests {
+    use super::*;
+    use futures::stream;
+    use wasm_bindgen_test::*;
+
+    wasm_bindgen_test_configure!(run_in_browser);
+
+    #[wasm_bindgen_test]
+    async fn next_timeout_on_empty_stream() {
+        let ti = TimeoutStream::with_stream(stream::pending::<u32>())
+            .await
+            .unwrap();
+        futures::pin_mut!(ti);
+        assert!(matches!(
+            ti.next_timeout(Duration::from_millis(20)).await,
+            Err(Error::TimedOut)
+        ));
+    }
 }
@@ -1,12 +1,27 @@
 use std::fmt;
 use std::fmt::{Debug, Formatter, Result as FmtResult};
 
+/// The error of both the synchronous and the asynchronous side. There being just the one,
+/// code bridging the two propagates it with `?` as it is, no conversion needed. Variants
+/// that only the thread-backed side produces are behind the `sync` feature.
+///
+/// Which variants exist depends on the features enabled, and more may come, so matches on
+/// it need a wildcard arm.
 #[derive(Debug)]
+#[non_exhaustive]
 pub enum Error {
     #[cfg(feature = "sync")]
     ErrorSpawningThread(std::io::Error),
     TimedOut,
     Disconnected,
+    #[cfg(any(feature = "sync", feature = "tokio-util"))]
+    Cancelled,
+    #[cfg(feature = "sync")]
+    ConditionMet,
+    #[cfg(feature = "sync")]
+    BufferOverflow,
+    #[cfg(feature = "sync")]
+    LookaheadExceeded,
 }
 
 impl std::error::Error for Error {}
@@ -19,6 +34,18 @@ impl fmt::Display for Error {
                 Self::TimedOut =>
                     "Timed out waiting on the underlying iterator for the next item".to_owned(),
                 Self::Disconnected => "Underlying iterator closed/disconnected".to_owned(),
+                #[cfg(any(feature = "sync", feature = "tokio-util"))]
+                Self::Cancelled => "Cancelled while waiting on the underlying iterator".to_owned(),
+                #[cfg(feature = "sync")]
+                Self::ConditionMet =>
+                    "Condition met while waiting on the underlying iterator".to_owned(),
+                #[cfg(feature = "sync")]
+                Self::BufferOverflow =>
+                    "Underlying iterator stopped for getting too far ahead of the consumer"
+                        .to_owned(),
+                #[cfg(feature = "sync")]
+                Self::LookaheadExceeded =>
+                    "Peeking would buffer more items than the lookahead limit allows".to_owned(),
 
                 #[cfg(feature = "sync")]
                 Self::ErrorSpawningThread(e) => format!(
@@ -36,3 +63,70 @@ impl From<std::io::Error> for Error {
         Self::ErrorSpawningThread(err)
     }
 }
+
+/// Failure of a `try_for_each_timeout`: either the callback's own error or a problem waiting on
+/// the stream, such as `Error::TimedOut`.
+#[derive(Debug)]
+pub enum TryForEachError<E> {
+    Callback(E),
+    Stream(Error),
+}
+
+impl<E: Debug + fmt::Display> std::error::Error for TryForEachError<E> {}
+impl<E: fmt::Display> fmt::Display for TryForEachError<E> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Callback(e) => write!(f, "TimeoutIteratorError:: Callback failed: {}", e),
+            Self::Stream(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl<E> From<Error> for TryForEachError<E> {
+    fn from(err: Error) -> Self {
+        Self::Stream(err)
+    }
+}
+
+/// Failure of a `next_timeout_flat`: either the item itself was an error, such as the
+/// `io::Error` of a lines iterator, or there was a problem waiting on the source.
+#[cfg(feature = "sync")]
+#[derive(Debug)]
+pub enum FlatError<E> {
+    Item(E),
+    Iterator(Error),
+}
+
+#[cfg(feature = "sync")]
+impl<E: Debug + fmt::Display> std::error::Error for FlatError<E> {}
+#[cfg(feature = "sync")]
+impl<E: fmt::Display> fmt::Display for FlatError<E> {
+    fn fmt(&self, f: &mut Formatter) -> FmtResult {
+        match self {
+            Self::Item(e) => write!(f, "TimeoutIteratorError:: Item was an error: {}", e),
+            Self::Iterator(e) => write!(f, "{}", e),
+        }
+    }
+}
+
+#[cfg(feature = "sync")]
+impl<E> From<Error> for FlatError<E> {
+    fn from(err: Error) -> Self {
+        Self::Iterator(err)
+    }
+}
+
+/// Lets `?` on a `next_timeout_flat` work in functions returning `io::Result`. Timeouts keep
+/// `ErrorKind::TimedOut`.
+#[cfg(feature = "sync")]
+impl From<FlatError<std::io::Error>> for std::io::Error {
+    fn from(err: FlatError<std::io::Error>) -> Self {
+        match err {
+            FlatError::Item(e) => e,
+            FlatError::Iterator(e @ Error::TimedOut) => {
+                std::io::Error::new(std::io::ErrorKind::TimedOut, e)
+            }
+            FlatError::Iterator(e) => std::io::Error::other(e),
+        }
+    }
+}
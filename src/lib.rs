@@ -3,5 +3,14 @@ pub mod error;
 #[cfg(feature = "sync")]
 pub mod synchronous;
 
+#[cfg(feature = "sync")]
+pub mod pool;
+
+#[cfg(feature = "sync")]
+pub mod testing;
+
+#[cfg(feature = "sync")]
+pub mod local;
+
 #[cfg(feature = "async")]
 pub mod asynchronous;
@@ -0,0 +1,152 @@
+use crate::error;
+use crate::synchronous::TimeoutPeekable;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// A single-threaded counterpart of `TimeoutIterator` for items that can't be sent to
+/// another thread, such as `Rc`s.
+///
+/// No sender thread is spawned: the source is pulled inline, on the caller's thread, so it
+/// should be one that is ready right away, such as items already in memory. Such a source
+/// can't be waited on either, so timeouts degenerate to "available now" and an exhausted
+/// source is `Disconnected`. A source whose `next` blocks blocks the caller for as long.
+pub struct LocalTimeoutIterator<I: Iterator> {
+    source: I,
+    buffer: VecDeque<I::Item>,
+}
+
+impl<I: Iterator> LocalTimeoutIterator<I> {
+    pub fn with_iter(iter: I) -> LocalTimeoutIterator<I> {
+        LocalTimeoutIterator {
+            source: iter,
+            buffer: VecDeque::new(),
+        }
+    }
+
+    /// Same contract as `TimeoutIterator::next_timeout`. The source is never waited on, so
+    /// `timeout` doesn't come into it.
+    pub fn next_timeout(&mut self, _timeout: Duration) -> Result<I::Item, error::Error> {
+        match self.buffer.pop_front() {
+            Some(item) => Ok(item),
+            None => self.source.next().ok_or(error::Error::Disconnected),
+        }
+    }
+
+    /// Same contract as `TimeoutIterator::peek_timeout`. The source is never waited on, so
+    /// `timeout` doesn't come into it.
+    pub fn peek_timeout(&mut self, _timeout: Duration) -> Result<&I::Item, error::Error> {
+        self.peek().ok_or(error::Error::Disconnected)
+    }
+
+    pub fn peek(&mut self) -> Option<&I::Item> {
+        if self.buffer.is_empty() {
+            let item = self.source.next()?;
+            self.buffer.push_back(item);
+        }
+        self.buffer.front()
+    }
+}
+
+impl<I: Iterator> Iterator for LocalTimeoutIterator<I> {
+    type Item = I::Item;
+
+    fn next(&mut self) -> Option<I::Item> {
+        self.buffer.pop_front().or_else(|| self.source.next())
+    }
+}
+
+impl<I: Iterator> TimeoutPeekable for LocalTimeoutIterator<I> {
+    fn peek(&mut self) -> Option<&I::Item> {
+        LocalTimeoutIterator::peek(self)
+    }
+}
+
+/// Lines of an in-memory text as `&str` slices into it, so that high-throughput parsing
+/// doesn't allocate a `String` per line as `BufRead::lines` would.
+///
+/// The text is owned by the `BorrowedLines` itself, and each line borrows it, so a line has
+/// to be let go of before asking for the next one. That rules out `Iterator`, but gives the
+/// same `next_timeout`/`peek_timeout` surface as `LocalTimeoutIterator`. Lines are split as
+/// by `str::lines`.
+pub struct BorrowedLines {
+    text: String,
+    // Start of the next line in `text`
+    position: usize,
+}
+
+impl BorrowedLines {
+    pub fn new(text: impl Into<String>) -> BorrowedLines {
+        BorrowedLines {
+            text: text.into(),
+            position: 0,
+        }
+    }
+
+    /// Same contract as `TimeoutIterator::next_timeout`. The text is all in memory, so
+    /// `timeout` doesn't come into it.
+    pub fn next_timeout(&mut self, _timeout: Duration) -> Result<&str, error::Error> {
+        let (start, end, next) = self.locate().ok_or(error::Error::Disconnected)?;
+        self.position = next;
+        Ok(&self.text[start..end])
+    }
+
+    /// Same contract as `TimeoutIterator::peek_timeout`. The text is all in memory, so
+    /// `timeout` doesn't come into it.
+    pub fn peek_timeout(&self, _timeout: Duration) -> Result<&str, error::Error> {
+        let (start, end, _) = self.locate().ok_or(error::Error::Disconnected)?;
+        Ok(&self.text[start..end])
+    }
+
+    /// Where the next line starts and ends, and where the one after it starts.
+    fn locate(&self) -> Option<(usize, usize, usize)> {
+        let start = self.position;
+        let rest = self.text.get(start..).filter(|rest| !rest.is_empty())?;
+
+        let (mut end, next) = match rest.find('\n') {
+            Some(newline) => (start + newline, start + newline + 1),
+            None => (self.text.len(), self.text.len()),
+        };
+        if self.text[start..end].ends_with('\r') {
+            end -= 1;
+        }
+        Some((start, end, next))
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::*;
+    use assert_matches::assert_matches;
+    use std::rc::Rc;
+
+    #[test]
+    fn serves_rc_items() {
+        let items: Vec<Rc<u32>> = (1..=3).map(Rc::new).collect();
+        let mut ti = LocalTimeoutIterator::with_iter(items.into_iter());
+
+        assert_eq!(**ti.peek_timeout(Duration::from_secs(1)).unwrap(), 1);
+        assert_eq!(*ti.next_timeout(Duration::from_secs(1)).unwrap(), 1);
+        assert_eq!(*ti.next().unwrap(), 2);
+        assert_eq!(**ti.peek().unwrap(), 3);
+        assert_eq!(*ti.next_timeout(Duration::from_secs(1)).unwrap(), 3);
+
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)),
+            Err(error::Error::Disconnected)
+        );
+        assert!(ti.peek().is_none());
+    }
+
+    #[test]
+    fn borrowed_lines_slice_the_text() {
+        let mut lines = BorrowedLines::new("first\r\nsecond\n\nlast");
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(lines.peek_timeout(timeout).unwrap(), "first");
+        assert_eq!(lines.next_timeout(timeout).unwrap(), "first");
+        assert_eq!(lines.next_timeout(timeout).unwrap(), "second");
+        assert_eq!(lines.next_timeout(timeout).unwrap(), "");
+        assert_eq!(lines.next_timeout(timeout).unwrap(), "last");
+        assert_matches!(lines.next_timeout(timeout), Err(error::Error::Disconnected));
+    }
+}
@@ -0,0 +1,202 @@
+use crate::error;
+use crate::synchronous::{Message, Registration, SenderState, SenderStatus};
+use std::collections::VecDeque;
+use std::fmt;
+use std::panic;
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::thread;
+
+/// A fixed set of worker threads that drive the sources of many `TimeoutIterator`s, as an
+/// alternative to spawning one sender thread per iterator.
+///
+/// Workers take turns pulling one item at a time from each source, so a busy source can't
+/// monopolize the pool. The tradeoff is that a source whose `next` blocks holds its worker
+/// for as long as it blocks: with every worker stuck on a quiet source, no other iterator on
+/// the pool makes progress and their timeouts fire. Sources that may block indefinitely are
+/// better served by dedicated threads.
+///
+/// Workers exit once every handle to the pool (including those held by iterators built on
+/// it) is gone and their remaining sources have finished.
+#[derive(Clone)]
+pub struct SenderPool {
+    handle: Arc<PoolHandle>,
+}
+
+impl SenderPool {
+    /// Starts `workers` threads, of which there must be at least one.
+    pub fn new(workers: usize) -> Result<SenderPool, error::Error> {
+        if workers == 0 {
+            return Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                "a sender pool needs at least one worker",
+            )
+            .into());
+        }
+
+        let shared = Arc::new(Shared {
+            queue: Mutex::new(Queue {
+                jobs: VecDeque::new(),
+                running: 0,
+                closed: false,
+            }),
+            available: Condvar::new(),
+        });
+
+        for _ in 0..workers {
+            let shared = shared.clone();
+            thread::Builder::new()
+                .name("TimeoutIterator::pool".to_owned())
+                .spawn(move || shared.work())?;
+        }
+
+        Ok(SenderPool {
+            handle: Arc::new(PoolHandle { shared, workers }),
+        })
+    }
+
+    /// Starts draining `iter` into `sink` on the pool.
+    pub(crate) fn spawn_sender<T, R>(
+        &self,
+        iter: R,
+        sink: mpsc::Sender<Message<T>>,
+        status: SenderStatus,
+        max_buffered: Option<usize>,
+        registration: Registration,
+    ) where
+        T: Send + 'static,
+        R: Iterator<Item = T> + Send + 'static,
+    {
+        self.handle.shared.submit(Box::new(Pumped {
+            iter,
+            sink,
+            status,
+            max_buffered,
+            registration,
+        }));
+    }
+}
+
+impl fmt::Debug for SenderPool {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("SenderPool")
+            .field("workers", &self.handle.workers)
+            .finish()
+    }
+}
+
+/// A source with its sink, advanced one item per turn on a worker.
+trait Pump: Send {
+    /// Moves one item across; false once the source is done or nobody is listening.
+    fn pump(&mut self) -> bool;
+
+    /// Records that the source panicked during a `pump`.
+    fn panicked(&self);
+}
+
+struct Pumped<T, R> {
+    iter: R,
+    sink: mpsc::Sender<Message<T>>,
+    status: SenderStatus,
+    max_buffered: Option<usize>,
+    registration: Registration,
+}
+
+impl<T, R> Pump for Pumped<T, R>
+where
+    T: Send,
+    R: Iterator<Item = T> + Send,
+{
+    fn pump(&mut self) -> bool {
+        self.registration.pass();
+        match self.iter.next() {
+            Some(item) => {
+                if !self.status.hand_over(self.max_buffered) {
+                    return false;
+                }
+                if self.sink.send(Message::Item(item)).is_err() {
+                    self.status.set(SenderState::ReceiverDropped);
+                    return false;
+                }
+                true
+            }
+            None => {
+                self.status.set(SenderState::Finished);
+                false
+            }
+        }
+    }
+
+    fn panicked(&self) {
+        self.status.set(SenderState::Panicked);
+    }
+}
+
+struct Queue {
+    jobs: VecDeque<Box<dyn Pump>>,
+    running: usize,
+    closed: bool,
+}
+
+struct Shared {
+    queue: Mutex<Queue>,
+    available: Condvar,
+}
+
+impl Shared {
+    fn submit(&self, job: Box<dyn Pump>) {
+        self.queue.lock().unwrap().jobs.push_back(job);
+        self.available.notify_one();
+    }
+
+    fn work(&self) {
+        let mut queue = self.queue.lock().unwrap();
+        loop {
+            let mut job = match queue.jobs.pop_front() {
+                Some(job) => job,
+                None if queue.closed && queue.running == 0 => {
+                    // Let any other idle worker see that it's time to go too
+                    self.available.notify_all();
+                    return;
+                }
+                None => {
+                    queue = self.available.wait(queue).unwrap();
+                    continue;
+                }
+            };
+
+            queue.running += 1;
+            drop(queue);
+            // A panicking source costs only its own job, not the worker
+            let more = match panic::catch_unwind(panic::AssertUnwindSafe(|| job.pump())) {
+                Ok(more) => more,
+                Err(_) => {
+                    job.panicked();
+                    false
+                }
+            };
+            queue = self.queue.lock().unwrap();
+            queue.running -= 1;
+
+            if more {
+                queue.jobs.push_back(job);
+                self.available.notify_one();
+            } else if queue.closed {
+                self.available.notify_all();
+            }
+        }
+    }
+}
+
+/// Closes the pool once the last `SenderPool` clone is dropped.
+struct PoolHandle {
+    shared: Arc<Shared>,
+    workers: usize,
+}
+
+impl Drop for PoolHandle {
+    fn drop(&mut self) {
+        self.shared.queue.lock().unwrap().closed = true;
+        self.shared.available.notify_all();
+    }
+}
@@ -1,236 +1,4181 @@
-use std::time::Duration;
+use std::time::{Duration, Instant};
 
 use crate::error;
+use crate::pool::SenderPool;
+use std::collections::VecDeque;
+use std::fmt;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicU8, AtomicUsize, Ordering};
 use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
 use std::thread;
 
-pub struct TimeoutIterator<T> {
-    source: mpsc::Receiver<T>,
-    buffer: Vec<T>,
+#[cfg(feature = "async")]
+use futures::{FutureExt, StreamExt};
+
+/// How many already-queued items `next_timeout` moves into the buffer by default.
+const DEFAULT_BATCH_SIZE: usize = 16;
+
+/// How often `next_timeout_or` checks its condition while waiting, by default.
+const DEFAULT_TICK: Duration = Duration::from_millis(10);
+
+/// The source of time for `TimeoutIterator`: every timed receive goes through it.
+///
+/// `SystemClock` is the real thing. `testing::MockClock` lets tests move time forward
+/// by hand instead of sleeping.
+pub trait Clock {
+    fn now(&self) -> Instant;
+
+    /// Same contract as `mpsc::Receiver::recv_timeout`, with `timeout` measured by this clock.
+    fn recv_timeout<T>(
+        &self,
+        source: &mpsc::Receiver<T>,
+        timeout: Duration,
+    ) -> Result<T, mpsc::RecvTimeoutError>;
 }
 
-impl<T> TimeoutIterator<T>
-where
-    T: Send + 'static,
-{
-    pub fn with_iter<R>(iter: R) -> Result<TimeoutIterator<T>, error::Error>
+#[derive(Clone, Copy, Debug, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> Instant {
+        Instant::now()
+    }
+
+    fn recv_timeout<T>(
+        &self,
+        source: &mpsc::Receiver<T>,
+        timeout: Duration,
+    ) -> Result<T, mpsc::RecvTimeoutError> {
+        source.recv_timeout(timeout)
+    }
+}
+
+/// Configures the sender thread that drives a `TimeoutIterator`'s source. The same
+/// configuration is reused for any source swapped in later.
+#[derive(Clone, Debug)]
+pub struct Builder {
+    name: String,
+    stack_size: Option<usize>,
+    pool: Option<SenderPool>,
+    max_buffered: Option<usize>,
+    spawn_attempts: usize,
+    spawn_backoff: Duration,
+}
+
+impl Builder {
+    pub fn new() -> Builder {
+        Builder {
+            name: "TimeoutIterator::sender".to_owned(),
+            stack_size: None,
+            pool: None,
+            max_buffered: None,
+            spawn_attempts: 1,
+            spawn_backoff: Duration::ZERO,
+        }
+    }
+
+    /// Names the sender thread, which shows up in panic messages and debuggers.
+    pub fn name(mut self, name: impl Into<String>) -> Builder {
+        self.name = name.into();
+        self
+    }
+
+    /// Stack size for the sender thread, for sources that recurse deeply.
+    pub fn stack_size(mut self, size: usize) -> Builder {
+        self.stack_size = Some(size);
+        self
+    }
+
+    /// Drives sources on `pool` instead of giving each its own thread. Name and stack
+    /// size don't apply to pooled sources. See `SenderPool` for the tradeoffs.
+    pub fn pool(mut self, pool: SenderPool) -> Builder {
+        self.pool = Some(pool);
+        self
+    }
+
+    /// A safety valve against a runaway producer filling memory: once `max` items have been
+    /// handed over without being consumed, the sender gives up on the source instead of
+    /// sending more. The consumer gets the items already handed over, and then
+    /// `Error::BufferOverflow` where it would otherwise see `Disconnected`.
+    pub fn max_buffered(mut self, max: usize) -> Builder {
+        self.max_buffered = Some(max);
+        self
+    }
+
+    /// Makes up to `attempts` tries at spawning the sender thread before giving up with
+    /// `ErrorSpawningThread`, waiting `backoff` after the first failure and twice as long
+    /// after each one after that. This rides out a transient shortage of threads.
+    pub fn spawn_retries(mut self, attempts: usize, backoff: Duration) -> Builder {
+        self.spawn_attempts = attempts.max(1);
+        self.spawn_backoff = backoff;
+        self
+    }
+
+    pub fn with_iter<T, R>(self, iter: R) -> Result<TimeoutIterator<T>, error::Error>
     where
+        T: Send + 'static,
         R: Iterator<Item = T> + Send + 'static,
     {
-        let (sink, source): (mpsc::Sender<T>, mpsc::Receiver<T>) = mpsc::channel();
+        let controls = Arc::new(Controls::default());
+        let (source, status) = self.spawn_sender(iter, &controls)?;
+        Ok(TimeoutIterator::new(
+            Source::Sender(source),
+            Vec::new(),
+            status,
+            controls,
+            self,
+        ))
+    }
 
-        thread::Builder::new().name("TimeoutIterator::sender".to_owned()).spawn(move || {
-            for item in iter {
-                if let Err(e) = sink.send(item) {
-                    eprintln!("TimeoutIterator:: Error sending data to channel. Receiver may have closed. Closing up sender. Error: {}", e);
-                    return;
-                }
+    /// Drains `iter` into a new channel from a dedicated thread, returning the receiving end.
+    fn spawn_sender<T, R>(
+        &self,
+        iter: R,
+        controls: &Arc<Controls>,
+    ) -> Result<(mpsc::Receiver<Message<T>>, SenderStatus), error::Error>
+    where
+        T: Send + 'static,
+        R: Iterator<Item = T> + Send + 'static,
+    {
+        let status = SenderStatus {
+            track_arrivals: controls.track_arrivals.clone(),
+            ..SenderStatus::new(SenderState::Running)
+        };
+
+        let (sink, source) = mpsc::channel();
+        let registration = controls.register(sink.clone());
+
+        if let Some(pool) = &self.pool {
+            pool.spawn_sender(iter, sink, status.clone(), self.max_buffered, registration);
+            return Ok((source, status));
+        }
+
+        let thread_status = status.clone();
+        let max_buffered = self.max_buffered;
+
+        self.spawn_thread(move || {
+            let status = PanicGuard(thread_status);
+            if forward(iter, &sink, &status.0, max_buffered, &registration) {
+                status.0.set(SenderState::Finished);
             }
         })?;
 
-        Ok(TimeoutIterator {
-            source,
-            buffer: Vec::new(),
-        })
+        Ok((source, status))
     }
 
-    pub fn next_timeout(&mut self, timeout: Duration) -> Result<T, error::Error> {
-        if !self.buffer.is_empty() {
-            return Ok(self.buffer.remove(0));
-        };
+    /// Spawns a thread configured as this builder says to run `body`, retrying failed spawns.
+    fn spawn_thread(&self, body: impl FnOnce() + Send + 'static) -> std::io::Result<()> {
+        // A failed spawn drops the closure it was given, so `body` is kept out of it
+        let body = Arc::new(Mutex::new(Some(body)));
+        let mut backoff = self.spawn_backoff;
 
-        match self.source.recv_timeout(timeout) {
-            Ok(item) => Ok(item),
-            Err(e) => match e {
-                mpsc::RecvTimeoutError::Timeout => Err(error::Error::TimedOut),
-                mpsc::RecvTimeoutError::Disconnected => Err(error::Error::Disconnected),
-            },
+        for attempt in 1.. {
+            let mut thread_builder = thread::Builder::new().name(self.name.clone());
+            if let Some(size) = self.stack_size {
+                thread_builder = thread_builder.stack_size(size);
+            }
+
+            let job = body.clone();
+            let spawned = spawn(thread_builder, move || {
+                let body = job.lock().unwrap().take();
+                if let Some(body) = body {
+                    body();
+                }
+            });
+
+            match spawned {
+                Ok(()) => return Ok(()),
+                Err(e) if attempt >= self.spawn_attempts => return Err(e),
+                Err(_) => {
+                    thread::sleep(backoff);
+                    backoff *= 2;
+                }
+            }
         }
+        unreachable!("attempts are unbounded")
     }
+}
 
-    pub fn peek_timeout(&mut self, timeout: Duration) -> Result<&T, error::Error> {
-        if self.buffer.is_empty() {
-            match self.source.recv_timeout(timeout) {
-                Ok(item) => self.buffer.push(item),
-                Err(e) => match e {
-                    mpsc::RecvTimeoutError::Timeout => return Err(error::Error::TimedOut),
-                    mpsc::RecvTimeoutError::Disconnected => return Err(error::Error::Disconnected),
-                },
-            }
-        };
+#[cfg(not(test))]
+fn spawn(builder: thread::Builder, f: impl FnOnce() + Send + 'static) -> std::io::Result<()> {
+    builder.spawn(f).map(|_| ())
+}
 
-        Ok(self.buffer.first().unwrap())
+#[cfg(test)]
+thread_local! {
+    /// How many of the spawns coming up on this thread should fail, to test recovery.
+    static FAILING_SPAWNS: std::cell::Cell<usize> = const { std::cell::Cell::new(0) };
+}
+
+#[cfg(test)]
+fn spawn(builder: thread::Builder, f: impl FnOnce() + Send + 'static) -> std::io::Result<()> {
+    let failing = FAILING_SPAWNS.with(|failing| failing.replace(failing.get().saturating_sub(1)));
+    if failing > 0 {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::WouldBlock,
+            "injected spawn failure",
+        ));
     }
+    builder.spawn(f).map(|_| ())
+}
 
-    pub fn peek(&mut self) -> Option<&T> {
-        if self.buffer.is_empty() {
-            match self.next() {
-                Some(item) => self.buffer.push(item),
-                None => {
-                    return None;
-                }
+/// Why the sender feeding a `TimeoutIterator` stopped, if it has.
+///
+/// From the consumer's side each of these looks like `Error::Disconnected`, apart from
+/// `Overflowed`, which is reported as `Error::BufferOverflow`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SenderState {
+    /// Still forwarding items from the source.
+    Running,
+    /// The source ran out of items.
+    Finished,
+    /// The iterator was dropped, so the sender stopped pulling from the source.
+    ReceiverDropped,
+    /// The source panicked.
+    Panicked,
+    /// Too many items were left unconsumed, see `Builder::max_buffered`.
+    Overflowed,
+}
+
+/// A shareable view of a sender's `SenderState`, which stays readable after the iterator it
+/// came from has been dropped.
+#[derive(Clone, Debug)]
+pub struct SenderStatus {
+    state: Arc<AtomicU8>,
+    // Items handed over by the sender and not consumed yet
+    unconsumed: Arc<AtomicUsize>,
+    arrivals: Arc<Mutex<Arrivals>>,
+    // Whether anyone has asked for `arrivals`, shared by every source of an iterator
+    track_arrivals: Arc<AtomicBool>,
+}
+
+impl SenderStatus {
+    pub(crate) fn new(state: SenderState) -> SenderStatus {
+        SenderStatus {
+            state: Arc::new(AtomicU8::new(state as u8)),
+            unconsumed: Arc::new(AtomicUsize::new(0)),
+            arrivals: Arc::default(),
+            track_arrivals: Arc::default(),
+        }
+    }
+
+    pub fn state(&self) -> SenderState {
+        match self.state.load(Ordering::Acquire) {
+            s if s == SenderState::Running as u8 => SenderState::Running,
+            s if s == SenderState::Finished as u8 => SenderState::Finished,
+            s if s == SenderState::ReceiverDropped as u8 => SenderState::ReceiverDropped,
+            s if s == SenderState::Overflowed as u8 => SenderState::Overflowed,
+            _ => SenderState::Panicked,
+        }
+    }
+
+    pub(crate) fn set(&self, state: SenderState) {
+        self.state.store(state as u8, Ordering::Release);
+    }
+
+    /// Accounts for one more item about to be sent, unless that would go over `max`, in which
+    /// case the sender is marked `Overflowed` and should stop.
+    pub(crate) fn hand_over(&self, max: Option<usize>) -> bool {
+        if let Some(max) = max {
+            if self.unconsumed.load(Ordering::Acquire) >= max {
+                self.set(SenderState::Overflowed);
+                return false;
             }
+        }
+        self.unconsumed.fetch_add(1, Ordering::AcqRel);
+        if self.track_arrivals.load(Ordering::Acquire) {
+            self.arrivals.lock().unwrap().arrived(Instant::now());
+        }
+        true
+    }
+
+    fn arrival_stats(&self) -> ArrivalStats {
+        self.track_arrivals.store(true, Ordering::Release);
+        self.arrivals.lock().unwrap().stats()
+    }
+
+    fn reset_arrivals(&self) {
+        let mut arrivals = self.arrivals.lock().unwrap();
+        // Keeping the last arrival lets the next gap count
+        *arrivals = Arrivals {
+            last: arrivals.last,
+            ..Arrivals::default()
         };
+    }
+
+    fn consumed(&self) {
+        let _ = self
+            .unconsumed
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |n| n.checked_sub(1));
+    }
 
-        Some(self.buffer.first().unwrap())
+    /// Accounts for `n` items put back to be consumed (again), so consuming them doesn't
+    /// free up room that the sender never used.
+    fn returned(&self, n: usize) {
+        self.unconsumed.fetch_add(n, Ordering::AcqRel);
     }
 }
 
-impl<T> Iterator for TimeoutIterator<T> {
-    type Item = T;
-    fn next(&mut self) -> Option<Self::Item> {
-        if !self.buffer.is_empty() {
-            return Some(self.buffer.remove(0));
-        };
+/// How far apart items have come out of the source, from `TimeoutIterator::arrival_stats`.
+/// Everything is `None` until at least two items have arrived.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ArrivalStats {
+    pub min: Option<Duration>,
+    pub max: Option<Duration>,
+    pub mean: Option<Duration>,
+}
 
-        match self.source.recv() {
-            Ok(item) => Some(item),
-            Err(e) => {
-                eprintln!(
-                    "TimeoutIterator:: Error occurred reading from source: {}.",
-                    e
-                );
-                None
-            }
+/// Running totals behind `ArrivalStats`, kept up to date by the sender.
+#[derive(Debug, Default)]
+struct Arrivals {
+    last: Option<Instant>,
+    gaps: u32,
+    total: Duration,
+    min: Option<Duration>,
+    max: Option<Duration>,
+}
+
+impl Arrivals {
+    fn arrived(&mut self, now: Instant) {
+        if let Some(last) = self.last.replace(now) {
+            let gap = now.saturating_duration_since(last);
+            self.gaps += 1;
+            self.total += gap;
+            self.min = Some(self.min.map_or(gap, |min| min.min(gap)));
+            self.max = Some(self.max.map_or(gap, |max| max.max(gap)));
+        }
+    }
+
+    fn stats(&self) -> ArrivalStats {
+        ArrivalStats {
+            min: self.min,
+            max: self.max,
+            mean: (self.gaps > 0).then(|| self.total / self.gaps),
         }
     }
 }
 
-#[cfg(all(test, feature = "sync"))]
-mod tests {
-    use super::*;
-    use std::io::prelude::*;
+/// What travels over the channel from a sender: the source's items, or a nudge from a
+/// `StopHandle` to wake up a blocked receive.
+pub(crate) enum Message<T> {
+    Item(T),
+    Stop,
+}
 
-    #[test]
-    fn iterates() {
-        let realistic_message = r"1
-2
-3
-4
-5";
-        let lines_iterator = (Box::new(realistic_message.as_bytes())).lines();
+/// Cancels a `TimeoutIterator`'s waits from another thread, from
+/// `TimeoutIterator::stop_handle`.
+///
+/// Once stopped, a receive that is in progress and every one after it fails with
+/// `Error::Cancelled`. Items already buffered are still handed out. Stopping is permanent.
+#[derive(Clone)]
+pub struct StopHandle {
+    signal: Arc<Controls>,
+}
 
-        let mut ti = TimeoutIterator::with_iter(lines_iterator).unwrap();
+impl StopHandle {
+    pub fn stop(&self) {
+        self.signal.stopped.store(true, Ordering::Release);
+        if let Some((_, wake)) = &*self.signal.wake.lock().unwrap() {
+            wake();
+        }
+    }
 
-        assert_eq!(ti.next().unwrap().unwrap(), "1");
-        assert_eq!(ti.next().unwrap().unwrap(), "2");
-        assert_eq!(ti.next().unwrap().unwrap(), "3");
-        assert_eq!(ti.next().unwrap().unwrap(), "4");
-        assert_eq!(ti.next().unwrap().unwrap(), "5");
+    pub fn is_stopped(&self) -> bool {
+        self.signal.is_stopped()
+    }
+}
+
+impl fmt::Debug for StopHandle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("StopHandle")
+            .field("stopped", &self.is_stopped())
+            .finish()
     }
+}
 
-    #[test]
-    fn next_timeout() {
-        let realistic_message = r"1
-2
-3
-4
-5";
-        let lines_iterator = (Box::new(realistic_message.as_bytes())).lines();
+/// Sends a `Message::Stop` down the current source's channel.
+type Wake = Box<dyn Fn() + Send>;
 
-        let mut ti = TimeoutIterator::with_iter(lines_iterator).unwrap();
+/// The state shared between a `TimeoutIterator`, its `StopHandle`s and its senders.
+///
+/// Waking a blocked receive takes a sender on its channel, but a sender held here would keep
+/// the channel from ever disconnecting. So the wakeup is only registered for as long as the
+/// thread (or pool job) feeding the current source holds its own sender anyway.
+#[derive(Default)]
+pub(crate) struct Controls {
+    stopped: AtomicBool,
+    wake: Mutex<Option<(u64, Wake)>>,
+    generation: AtomicU64,
+    gate: Mutex<Gate>,
+    resumed: Condvar,
+    track_arrivals: Arc<AtomicBool>,
+}
 
-        assert_eq!(ti.next().unwrap().unwrap(), "1");
-        assert_eq!(ti.next().unwrap().unwrap(), "2");
-        assert_eq!(ti.next().unwrap().unwrap(), "3");
-        assert_eq!(ti.next().unwrap().unwrap(), "4");
-        assert_eq!(ti.next().unwrap().unwrap(), "5");
+/// Whether senders may pull the next item from their source.
+#[derive(Default)]
+struct Gate {
+    paused: bool,
+    // Lets one item through while paused, see `Controls::let_one_through`
+    let_through: bool,
+}
 
-        let timeout_result = ti.next_timeout(Duration::from_secs(1));
-        assert!(timeout_result.is_err());
+impl Controls {
+    fn is_stopped(&self) -> bool {
+        self.stopped.load(Ordering::Acquire)
     }
 
-    #[test]
-    fn peek_timeout_doesnt_remove() {
-        let realistic_message = r"1
-2
-3
-4
-5";
-        let lines_iterator = (Box::new(realistic_message.as_bytes())).lines();
-        let mut ti = TimeoutIterator::with_iter(lines_iterator).unwrap();
+    fn set_paused(&self, paused: bool) {
+        self.gate.lock().unwrap().paused = paused;
+        self.resumed.notify_all();
+    }
 
-        assert_eq!(ti.next().unwrap().unwrap(), "1");
-        assert_eq!(ti.next().unwrap().unwrap(), "2");
-        assert_eq!(
-            ti.peek_timeout(Duration::from_secs(1))
-                .ok()
-                .unwrap()
-                .as_ref()
-                .unwrap(),
-            "3"
-        );
-        assert_eq!(ti.next().unwrap().unwrap(), "3");
-        assert_eq!(ti.next().unwrap().unwrap(), "4");
-        assert_eq!(
-            ti.peek_timeout(Duration::from_secs(1))
-                .ok()
-                .unwrap()
-                .as_ref()
-                .unwrap(),
-            "5"
-        );
-        assert_eq!(
-            ti.peek_timeout(Duration::from_secs(1))
-                .ok()
-                .unwrap()
-                .as_ref()
-                .unwrap(),
-            "5"
-        );
-        assert_eq!(ti.next().unwrap().unwrap(), "5");
+    /// Lets a paused sender pull one more item, for stepping through a source one item at a
+    /// time. It doesn't add up: letting one through twice before it is taken lets one through.
+    fn let_one_through(&self) {
+        self.gate.lock().unwrap().let_through = true;
+        self.resumed.notify_all();
+    }
 
-        let timeout_result = ti.next_timeout(Duration::from_secs(1));
-        assert!(timeout_result.is_err());
+    /// Makes `sink` the channel to wake on a stop, until the returned registration is dropped.
+    fn register<T: Send + 'static>(
+        self: &Arc<Self>,
+        sink: mpsc::Sender<Message<T>>,
+    ) -> Registration {
+        let generation = self.generation.fetch_add(1, Ordering::Relaxed);
+        let wake = Box::new(move || {
+            let _ = sink.send(Message::Stop);
+        });
+        *self.wake.lock().unwrap() = Some((generation, wake));
+
+        Registration {
+            signal: self.clone(),
+            generation,
+        }
     }
+}
 
-    #[test]
-    fn peek_doesnt_remove() {
-        let realistic_message = r"1
-2
-3
-4
-5";
-        let lines_iterator = (Box::new(realistic_message.as_bytes())).lines();
-        let mut ti = TimeoutIterator::with_iter(lines_iterator).unwrap();
+/// Keeps a sender's wakeup registered with its `Controls`, to be dropped alongside the
+/// sender itself. A registration for a replaced source leaves its successor's alone.
+pub(crate) struct Registration {
+    signal: Arc<Controls>,
+    generation: u64,
+}
 
-        assert_eq!(ti.next().unwrap().unwrap(), "1");
-        assert_eq!(ti.next().unwrap().unwrap(), "2");
-        assert_eq!(ti.peek().unwrap().as_ref().unwrap(), "3");
-        assert_eq!(ti.next().unwrap().unwrap(), "3");
-        assert_eq!(ti.next().unwrap().unwrap(), "4");
-        assert_eq!(ti.peek().unwrap().as_ref().unwrap(), "5");
-        assert_eq!(ti.peek().unwrap().as_ref().unwrap(), "5");
-        assert_eq!(ti.next().unwrap().unwrap(), "5");
+impl Registration {
+    /// Holds the sender back for as long as its iterator is paused, short of an item being
+    /// let through.
+    pub(crate) fn pass(&self) {
+        let mut gate = self.signal.gate.lock().unwrap();
+        while gate.paused && !gate.let_through {
+            gate = self.signal.resumed.wait(gate).unwrap();
+        }
+        gate.let_through = false;
+    }
+}
 
-        let timeout_result = ti.next_timeout(Duration::from_secs(1));
-        assert!(timeout_result.is_err());
+impl Drop for Registration {
+    fn drop(&mut self) {
+        let mut wake = self.signal.wake.lock().unwrap();
+        if matches!(&*wake, Some((generation, _)) if *generation == self.generation) {
+            *wake = None;
+        }
     }
+}
 
-    #[test]
-    fn item_iterator() {
-        let numbers: Vec<u32> = vec![1, 2, 3, 4, 5];
-        let mut ti = TimeoutIterator::with_iter(numbers.into_iter()).unwrap();
+/// The iterator's own hold on its `Controls`. Dropping it lets a paused sender go, so that it
+/// gets to find out nobody is listening any more and exit.
+struct OwnControls(Arc<Controls>);
 
-        assert_eq!(ti.next().unwrap(), 1);
-        assert_eq!(ti.next().unwrap(), 2);
-        assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).ok().unwrap(), 3);
-        assert_eq!(ti.next().unwrap(), 3);
-        assert_eq!(ti.next().unwrap(), 4);
-        assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).ok().unwrap(), 5);
-        assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).ok().unwrap(), 5);
-        assert_eq!(ti.next().unwrap(), 5);
+impl std::ops::Deref for OwnControls {
+    type Target = Arc<Controls>;
 
-        let timeout_result = ti.next_timeout(Duration::from_secs(1));
-        assert!(timeout_result.is_err());
+    fn deref(&self) -> &Arc<Controls> {
+        &self.0
     }
+}
 
-    #[test]
-    fn is_sendable() {
-        let numbers: Vec<u32> = vec![1, 2, 3, 4, 5];
-        let mut ti = TimeoutIterator::with_iter(numbers.into_iter()).unwrap();
-        thread::spawn(move || {
-            ti.next();
-        });
+impl Drop for OwnControls {
+    fn drop(&mut self) {
+        self.0.set_paused(false);
+    }
+}
+
+/// Moves the items of `iter` into `sink`, as a sender thread does. True if the source ran
+/// out, rather than overflowing or nobody listening any more, which `status` then says.
+fn forward<T, R>(
+    mut iter: R,
+    sink: &mpsc::Sender<Message<T>>,
+    status: &SenderStatus,
+    max_buffered: Option<usize>,
+    registration: &Registration,
+) -> bool
+where
+    R: Iterator<Item = T>,
+{
+    loop {
+        registration.pass();
+        let item = match iter.next() {
+            Some(item) => item,
+            None => return true,
+        };
+        if !status.hand_over(max_buffered) {
+            return false;
+        }
+        if sink.send(Message::Item(item)).is_err() {
+            status.set(SenderState::ReceiverDropped);
+            return false;
+        }
+    }
+}
+
+/// Marks the sender as `Panicked` if the source unwinds through it.
+struct PanicGuard(SenderStatus);
+
+impl Drop for PanicGuard {
+    fn drop(&mut self) {
+        if thread::panicking() {
+            self.0.set(SenderState::Panicked);
+        }
+    }
+}
+
+impl Default for Builder {
+    fn default() -> Builder {
+        Builder::new()
+    }
+}
+
+pub struct TimeoutIterator<T, C: Clock = SystemClock> {
+    source: Source<T>,
+    buffer: Buffer<T>,
+    batch_size: usize,
+    clock: C,
+    fallback: Option<Box<dyn Iterator<Item = T> + Send>>,
+    builder: Builder,
+    status: SenderStatus,
+    controls: OwnControls,
+    observed: Arc<Observed>,
+    recording: Option<Recording<T>>,
+    heartbeat: Option<Heartbeat<T>>,
+    end_marker: Option<Box<dyn FnOnce() -> T + Send>>,
+    last_timed_out: bool,
+    // Items left to consume, when known up front
+    remaining: Option<usize>,
+    tick: Duration,
+    adaptive: Option<Adaptive>,
+    max_lookahead: Option<usize>,
+}
+
+/// The channel a `TimeoutIterator` receives from: that of its own sender, or one it was
+/// handed by `from_receiver`.
+enum Source<T> {
+    Sender(mpsc::Receiver<Message<T>>),
+    Receiver(mpsc::Receiver<T>),
+}
+
+impl<T> Source<T> {
+    fn recv_timeout<C: Clock>(
+        &self,
+        clock: &C,
+        timeout: Duration,
+    ) -> Result<Message<T>, mpsc::RecvTimeoutError> {
+        match self {
+            Source::Sender(source) => clock.recv_timeout(source, timeout),
+            Source::Receiver(source) => clock.recv_timeout(source, timeout).map(Message::Item),
+        }
+    }
+
+    fn recv(&self) -> Result<Message<T>, mpsc::RecvError> {
+        match self {
+            Source::Sender(source) => source.recv(),
+            Source::Receiver(source) => source.recv().map(Message::Item),
+        }
+    }
+
+    fn try_recv(&self) -> Result<Message<T>, mpsc::TryRecvError> {
+        match self {
+            Source::Sender(source) => source.try_recv(),
+            Source::Receiver(source) => source.try_recv().map(Message::Item),
+        }
+    }
+}
+
+/// Items received from the channel but not consumed yet, in order.
+///
+/// A deque rather than a `Vec` since items are always taken from the front. It doesn't
+/// allocate until something is first buffered, and then keeps its capacity, so the common
+/// peek-one-then-consume pattern settles into no allocations at all.
+///
+/// Its length is mirrored into `Observed` for `StateHandle`, so items only come and go
+/// through its own `push_back`, `push_front` and `pop_front`, which shadow the deque's.
+struct Buffer<T> {
+    items: VecDeque<T>,
+    on_drop: Option<Box<dyn FnMut(T) + Send>>,
+    observed: Arc<Observed>,
+}
+
+impl<T> Buffer<T> {
+    fn new(items: Vec<T>, observed: Arc<Observed>) -> Buffer<T> {
+        observed.buffered.store(items.len(), Ordering::Release);
+        Buffer {
+            items: items.into(),
+            on_drop: None,
+            observed,
+        }
+    }
+
+    fn push_back(&mut self, item: T) {
+        self.items.push_back(item);
+        self.mirror_len();
+    }
+
+    fn push_front(&mut self, item: T) {
+        self.items.push_front(item);
+        self.mirror_len();
+    }
+
+    fn pop_front(&mut self) -> Option<T> {
+        let item = self.items.pop_front();
+        self.mirror_len();
+        item
+    }
+
+    fn mirror_len(&self) {
+        self.observed
+            .buffered
+            .store(self.items.len(), Ordering::Release);
+    }
+}
+
+impl<T> std::ops::Deref for Buffer<T> {
+    type Target = VecDeque<T>;
+
+    fn deref(&self) -> &VecDeque<T> {
+        &self.items
+    }
+}
+
+impl<T> std::ops::DerefMut for Buffer<T> {
+    fn deref_mut(&mut self) -> &mut VecDeque<T> {
+        &mut self.items
+    }
+}
+
+impl<T> Drop for Buffer<T> {
+    fn drop(&mut self) {
+        if let Some(on_drop) = &mut self.on_drop {
+            self.items.drain(..).for_each(on_drop);
+        }
+    }
+}
+
+/// What a `StateHandle` sees, kept up to date by the iterator.
+#[derive(Debug, Default)]
+struct Observed {
+    buffered: AtomicUsize,
+    received: AtomicUsize,
+    // Set once the channel has been seen to disconnect with no fallback left
+    disconnected: AtomicBool,
+}
+
+/// `TimeoutIterator::state_handle`.
+///
+/// Observes the iterator from anywhere, such as another thread, while its owner keeps
+/// borrowing it mutably.
+#[derive(Clone, Debug)]
+pub struct StateHandle {
+    observed: Arc<Observed>,
+}
+
+impl StateHandle {
+    /// How many items are received but not consumed yet.
+    pub fn buffered_len(&self) -> usize {
+        self.observed.buffered.load(Ordering::Acquire)
+    }
+
+    /// How many items have been taken off the channel so far, from every source in turn.
+    /// Heartbeats don't count.
+    pub fn items_received(&self) -> usize {
+        self.observed.received.load(Ordering::Acquire)
+    }
+
+    /// Whether the source has been seen to end, with no fallback left.
+    pub fn is_disconnected(&self) -> bool {
+        self.observed.disconnected.load(Ordering::Acquire)
+    }
+}
+
+/// A position to `rewind` back to, from `TimeoutIterator::checkpoint`.
+#[must_use = "recording continues until the checkpoint is rewound or committed"]
+#[derive(Debug)]
+pub struct Checkpoint {
+    position: usize,
+}
+
+/// Synthetic items to put out whenever the source stays quiet for `interval`.
+struct Heartbeat<T> {
+    interval: Duration,
+    make: Box<dyn Fn() -> T + Send>,
+}
+
+/// What a wait that may be cut short by a heartbeat came back with.
+enum Arrival<T> {
+    Item(T),
+    Heartbeat(T),
+}
+
+/// Derives `next_timeout`'s timeout from the pace items have been arriving at.
+struct Adaptive {
+    factor: f64,
+    floor: Duration,
+    ceiling: Duration,
+    last_arrival: Option<Instant>,
+    // Moving average of the gaps between arrivals
+    average: Option<Duration>,
+}
+
+impl Adaptive {
+    /// Weight of the newest gap in the moving average.
+    const SMOOTHING: f64 = 0.25;
+
+    fn timeout(&self) -> Option<Duration> {
+        let average = self.average?;
+        Some(average.mul_f64(self.factor).clamp(self.floor, self.ceiling))
+    }
+
+    fn arrived(&mut self, now: Instant) {
+        if let Some(last) = self.last_arrival.replace(now) {
+            let gap = now.saturating_duration_since(last);
+            self.average = Some(match self.average {
+                Some(average) => {
+                    average.mul_f64(1.0 - Self::SMOOTHING) + gap.mul_f64(Self::SMOOTHING)
+                }
+                None => gap,
+            });
+        }
+    }
+}
+
+/// Copies of the items consumed while any checkpoint is outstanding.
+struct Recording<T> {
+    consumed: Vec<T>,
+    clone: fn(&T) -> T,
+    outstanding: usize,
+}
+
+impl<T> TimeoutIterator<T>
+where
+    T: Send + 'static,
+{
+    pub fn with_iter<R>(iter: R) -> Result<TimeoutIterator<T>, error::Error>
+    where
+        R: Iterator<Item = T> + Send + 'static,
+    {
+        Builder::new().with_iter(iter)
+    }
+
+    /// Like `with_iter`, but remembers the length of `iter` so that `remaining_hint` and
+    /// `size_hint` can tell how many items are left.
+    pub fn with_exact_iter<R>(iter: R) -> Result<TimeoutIterator<T>, error::Error>
+    where
+        R: ExactSizeIterator<Item = T> + Send + 'static,
+    {
+        let remaining = iter.len();
+        let mut ti = TimeoutIterator::with_iter(iter)?;
+        ti.remaining = Some(remaining);
+        Ok(ti)
+    }
+
+    /// Like `with_iter`, but the sender thread forwards at most one item per `min_interval`,
+    /// sleeping in between, like `throttle` from tokio-stream. A bursty source then can't
+    /// flood the channel.
+    pub fn with_throttled_iter<R>(
+        iter: R,
+        min_interval: Duration,
+    ) -> Result<TimeoutIterator<T>, error::Error>
+    where
+        R: Iterator<Item = T> + Send + 'static,
+    {
+        let mut iter = iter;
+        let mut last: Option<Instant> = None;
+        TimeoutIterator::with_iter(std::iter::from_fn(move || {
+            if let Some(last) = last {
+                thread::sleep(min_interval.saturating_sub(last.elapsed()));
+            }
+            let item = iter.next()?;
+            last = Some(Instant::now());
+            Some(item)
+        }))
+    }
+
+    /// Like `with_iter`, retrying a failed spawn of the sender thread as
+    /// `Builder::spawn_retries` does.
+    pub fn with_iter_retry<R>(
+        iter: R,
+        attempts: usize,
+        backoff: Duration,
+    ) -> Result<TimeoutIterator<T>, error::Error>
+    where
+        R: Iterator<Item = T> + Send + 'static,
+    {
+        Builder::new()
+            .spawn_retries(attempts, backoff)
+            .with_iter(iter)
+    }
+
+    /// Like `with_iter`, with the sender thread named `name`.
+    pub fn with_iter_named<R>(
+        iter: R,
+        name: impl Into<String>,
+    ) -> Result<TimeoutIterator<T>, error::Error>
+    where
+        R: Iterator<Item = T> + Send + 'static,
+    {
+        Builder::new().name(name).with_iter(iter)
+    }
+
+    /// Wraps items that are already in memory without spawning a sender thread.
+    ///
+    /// Everything is served straight from the buffer, and once it is exhausted every call
+    /// reports `Disconnected` immediately.
+    pub fn from_buffered(items: Vec<T>) -> TimeoutIterator<T> {
+        // The sender is dropped right away, leaving a channel that is already disconnected.
+        let (_, source) = mpsc::channel();
+
+        let status = SenderStatus::new(SenderState::Finished);
+        let controls = Arc::new(Controls::default());
+        TimeoutIterator::new(
+            Source::Sender(source),
+            items,
+            status,
+            controls,
+            Builder::new(),
+        )
+    }
+
+    /// Takes an owned snapshot of a source of references, such as `slice.iter()`. Borrows
+    /// can't be handed to a sender thread, so this is eager: it clones every item on the
+    /// calling thread before returning, and then serves them as `from_buffered` does. The
+    /// source therefore has to be finite, and a slow one blocks here rather than in the
+    /// timed calls, which then never wait. For lazy cloning of a `'static` source, use
+    /// `with_iter(iter.cloned())`.
+    pub fn from_cloned_snapshot<'a, R>(iter: R) -> TimeoutIterator<T>
+    where
+        R: Iterator<Item = &'a T>,
+        T: Clone + 'a,
+    {
+        TimeoutIterator::from_buffered(iter.cloned().collect())
+    }
+
+    /// Wraps a channel that some producer already feeds, with no thread of its own in
+    /// between. The producer dropping its senders ends the iterator. As there is no sender
+    /// to hold back, `pause` has no effect, and a `StopHandle` can't interrupt a wait that
+    /// is already in progress, only fail the ones after it.
+    pub fn from_receiver(receiver: mpsc::Receiver<T>) -> TimeoutIterator<T> {
+        let status = SenderStatus::new(SenderState::Running);
+        let controls = Arc::new(Controls::default());
+        TimeoutIterator::new(
+            Source::Receiver(receiver),
+            Vec::new(),
+            status,
+            controls,
+            Builder::new(),
+        )
+    }
+
+    fn new(
+        source: Source<T>,
+        buffer: Vec<T>,
+        status: SenderStatus,
+        controls: Arc<Controls>,
+        builder: Builder,
+    ) -> TimeoutIterator<T> {
+        let observed = Arc::new(Observed::default());
+        TimeoutIterator {
+            source,
+            buffer: Buffer::new(buffer, Arc::clone(&observed)),
+            status,
+            controls: OwnControls(controls),
+            observed,
+            builder,
+            batch_size: DEFAULT_BATCH_SIZE,
+            clock: SystemClock,
+            fallback: None,
+            recording: None,
+            heartbeat: None,
+            end_marker: None,
+            last_timed_out: false,
+            remaining: None,
+            tick: DEFAULT_TICK,
+            adaptive: None,
+            max_lookahead: None,
+        }
+    }
+}
+
+/// Merges several sources into one `TimeoutIterator`, each drained by a thread of its own,
+/// so timeouts apply to the combined stream. Items from one source keep their order, but
+/// how items from different sources interleave is nondeterministic. The merged source
+/// ends once every one of them has.
+///
+/// The threads all feed the one channel, so pausing holds every source back, and the
+/// sender status only turns `Finished` once they all have.
+pub fn merge<T>(
+    iters: Vec<Box<dyn Iterator<Item = T> + Send>>,
+) -> Result<TimeoutIterator<T>, error::Error>
+where
+    T: Send + 'static,
+{
+    let controls = Arc::new(Controls::default());
+    let status = SenderStatus {
+        track_arrivals: controls.track_arrivals.clone(),
+        ..SenderStatus::new(SenderState::Running)
+    };
+    let (sink, source) = mpsc::channel();
+    let registration = Arc::new(controls.register(sink.clone()));
+    let live = Arc::new(AtomicUsize::new(iters.len()));
+    if iters.is_empty() {
+        status.set(SenderState::Finished);
+    }
+
+    for iter in iters {
+        let (sink, registration, live) = (sink.clone(), registration.clone(), live.clone());
+        let thread_status = status.clone();
+        // On failure `source` is dropped on the way out, so threads already spawned exit
+        thread::Builder::new()
+            .name("TimeoutIterator::merge".to_owned())
+            .spawn(move || {
+                let status = PanicGuard(thread_status);
+                if forward(iter, &sink, &status.0, None, &registration)
+                    && live.fetch_sub(1, Ordering::AcqRel) == 1
+                {
+                    status.0.set(SenderState::Finished);
+                }
+            })?;
+    }
+
+    Ok(TimeoutIterator::new(
+        Source::Sender(source),
+        Vec::new(),
+        status,
+        controls,
+        Builder::new(),
+    ))
+}
+
+impl<T, C: Clock> TimeoutIterator<T, C>
+where
+    T: Send + 'static,
+{
+    /// Measures every timeout against `clock` from now on.
+    pub fn with_clock<D: Clock>(self, clock: D) -> TimeoutIterator<T, D> {
+        TimeoutIterator {
+            source: self.source,
+            buffer: self.buffer,
+            batch_size: self.batch_size,
+            clock,
+            fallback: self.fallback,
+            builder: self.builder,
+            status: self.status,
+            controls: self.controls,
+            observed: self.observed,
+            recording: self.recording,
+            heartbeat: self.heartbeat,
+            end_marker: self.end_marker,
+            last_timed_out: self.last_timed_out,
+            remaining: self.remaining,
+            tick: self.tick,
+            adaptive: self.adaptive,
+            max_lookahead: self.max_lookahead,
+        }
+    }
+
+    /// Caps how many items peeking may buffer: `fill_to`, `peek_two` and `peek_while` fail
+    /// with `Error::LookaheadExceeded` rather than buffer more than `n`. A safety rail
+    /// against a predicate that never fails growing the buffer without bound.
+    pub fn max_lookahead(mut self, n: usize) -> Self {
+        self.max_lookahead = Some(n);
+        self
+    }
+
+    /// Whether peeking `depth` items ahead is within `max_lookahead`.
+    fn check_lookahead(&self, depth: usize) -> Result<(), error::Error> {
+        match self.max_lookahead {
+            Some(max) if depth > max => Err(error::Error::LookaheadExceeded),
+            _ => Ok(()),
+        }
+    }
+
+    /// Swaps in a new source, typically after the old one has disconnected. Anything already
+    /// buffered is still served first; whatever the old source had yet to deliver is dropped.
+    pub fn replace_source<R>(&mut self, iter: R) -> Result<(), error::Error>
+    where
+        R: Iterator<Item = T> + Send + 'static,
+    {
+        let (source, status) = self.builder.spawn_sender(iter, &self.controls)?;
+        self.source = Source::Sender(source);
+        self.status = status;
+        self.observed.disconnected.store(false, Ordering::Release);
+        self.remaining = None;
+        Ok(())
+    }
+
+    /// True once the source has been seen to disconnect and nothing is left in the buffer,
+    /// meaning every further read will fail with `Disconnected`.
+    pub fn is_done(&self) -> bool {
+        self.observed.disconnected.load(Ordering::Acquire) && self.buffer.is_empty()
+    }
+
+    /// The gaps between items coming out of the current source so far, as seen by its sender
+    /// rather than the consumer, so buffering doesn't skew them. Tells a bursty source from a
+    /// steady one.
+    ///
+    /// Senders only start timing items the first time stats are asked for, so that nobody
+    /// pays for timestamps that never get read; call this once up front to measure from the
+    /// start. `from_receiver` has no sender of its own to see items arrive, so its stats
+    /// stay empty.
+    pub fn arrival_stats(&self) -> ArrivalStats {
+        self.status.arrival_stats()
+    }
+
+    /// Zeroes `arrival_stats` and the `StateHandle::items_received` count, to measure the
+    /// phases of a long-running consumer separately. Iteration carries on as before, and it
+    /// is fine for the sender to be mid-item.
+    pub fn reset_stats(&self) {
+        self.status.reset_arrivals();
+        self.observed.received.store(0, Ordering::Release);
+    }
+
+    /// How many items are left to consume, when that is known: from `with_exact_iter`, until
+    /// another source is swapped or chained in. Heartbeats don't count.
+    pub fn remaining_hint(&self) -> Option<usize> {
+        self.remaining
+    }
+
+    /// The state of the sender feeding the current source. This tells a clean end of the
+    /// source apart from a panic in it, and the returned handle can be kept to observe the
+    /// sender after the iterator itself is gone.
+    pub fn sender_status(&self) -> SenderStatus {
+        self.status.clone()
+    }
+
+    /// Makes `next` and `next_timeout` hand out `make()` whenever no real item arrives within
+    /// `interval`, so a consuming loop gets to do periodic work while the source is quiet.
+    /// `next_timeout` still times out when its own timeout is the shorter of the two.
+    /// Heartbeats are never buffered, so peeking only ever sees real items. Whether one is
+    /// due is checked once every tick (see `set_tick`), so it can come up to a tick late.
+    pub fn with_heartbeat(
+        mut self,
+        interval: Duration,
+        make: impl Fn() -> T + Send + 'static,
+    ) -> Self {
+        self.heartbeat = Some(Heartbeat {
+            interval,
+            make: Box::new(make),
+        });
+        self
+    }
+
+    /// Stops the sender from pulling more items from the source until `resume`, for flow
+    /// control while downstream is overloaded. Whatever was pulled before still comes
+    /// through, and after that waits time out. The sender only checks between items. A pooled
+    /// source holds on to its worker while paused.
+    pub fn pause(&self) {
+        self.controls.set_paused(true);
+    }
+
+    /// Undoes `pause`, letting the sender carry on from where it stopped.
+    pub fn resume(&self) {
+        self.controls.set_paused(false);
+    }
+
+    /// A handle for cancelling this iterator's waits from another thread. It carries over to
+    /// any source swapped in later.
+    pub fn stop_handle(&self) -> StopHandle {
+        StopHandle {
+            signal: Arc::clone(&self.controls),
+        }
+    }
+
+    /// A handle for watching the buffer and source without borrowing the iterator. It carries
+    /// over to any source swapped in later.
+    pub fn state_handle(&self) -> StateHandle {
+        StateHandle {
+            observed: Arc::clone(&self.observed),
+        }
+    }
+
+    /// Continues transparently from `fallback` once the current source ends, so the consumer
+    /// only sees `Disconnected` after both are exhausted. Chaining several fallbacks uses them
+    /// in order.
+    pub fn chain_on_disconnect<R>(mut self, fallback: R) -> Self
+    where
+        R: Iterator<Item = T> + Send + 'static,
+    {
+        self.remaining = None;
+        self.fallback = Some(match self.fallback.take() {
+            Some(existing) => Box::new(existing.chain(fallback)),
+            None => Box::new(fallback),
+        });
+        self
+    }
+
+    /// Adds one last item, made by `make_end`, for when the source finishes normally, as an
+    /// explicit end-of-stream token. It comes after every fallback, and whatever source is
+    /// current at the time, including one swapped in by `replace_source` or the producer of
+    /// `from_receiver` hanging up, and `Disconnected` only after it. A source that panics,
+    /// overflows or is stopped gets no marker, so it only counts towards the upper bound of
+    /// `size_hint`, not `remaining_hint`.
+    pub fn with_end_marker(mut self, make_end: impl FnOnce() -> T + Send + 'static) -> Self {
+        self.end_marker = Some(Box::new(make_end));
+        self
+    }
+
+    /// Registers `f` to receive every item still buffered (peeked but never consumed) when
+    /// the iterator is dropped, so in-flight work isn't silently lost. Items still in the
+    /// channel, or not yet pulled from the source, are not included.
+    pub fn on_drop_flush(&mut self, f: impl FnMut(T) + Send + 'static) {
+        self.buffer.on_drop = Some(Box::new(f));
+    }
+
+    /// How often waits check on something other than an item: whether a heartbeat is due,
+    /// or the condition of `next_timeout_or`. A finer tick makes heartbeats and conditions
+    /// noticed closer to on time at the cost of more wakeups while idle; the default of 10ms
+    /// suits most uses. Items aren't polled for, so they arrive right away whatever the tick,
+    /// and timeouts expire on time.
+    pub fn set_tick(&mut self, tick: Duration) {
+        self.tick = tick;
+    }
+
+    /// Caps how many already-queued items `next_timeout` opportunistically moves into the
+    /// buffer after each successful receive. Bursty producers then cost one wakeup per batch
+    /// rather than one per item. Zero disables batching.
+    pub fn set_batch_size(&mut self, batch_size: usize) {
+        self.batch_size = batch_size;
+    }
+
+    pub fn next_timeout(&mut self, timeout: Duration) -> Result<T, error::Error> {
+        let timeout = self
+            .adaptive
+            .as_ref()
+            .and_then(Adaptive::timeout)
+            .unwrap_or(timeout);
+        self.next_within(timeout)
+    }
+
+    /// Sets `next_timeout` to work out its own timeout from how fast items have been arriving:
+    /// a moving average of the gaps between them, times `factor`, kept within `floor` and
+    /// `ceiling`. The timeout passed to `next_timeout` only applies until there is a gap to
+    /// go by. Helpers working to a deadline of their own are unaffected.
+    ///
+    /// Only items `next_timeout` actually waited for count towards the average; ones that were
+    /// already buffered or queued say nothing about the producer's pace. Panics unless `factor`
+    /// is positive and finite and `floor` is no greater than `ceiling`.
+    pub fn enable_adaptive_timeout(&mut self, factor: f64, floor: Duration, ceiling: Duration) {
+        assert!(
+            factor.is_finite() && factor > 0.0,
+            "adaptive timeout factor must be positive and finite, not {}",
+            factor
+        );
+        assert!(
+            floor <= ceiling,
+            "adaptive timeout floor {:?} is above its ceiling {:?}",
+            floor,
+            ceiling
+        );
+
+        self.adaptive = Some(Adaptive {
+            factor,
+            floor,
+            ceiling,
+            last_arrival: None,
+            average: None,
+        });
+    }
+
+    /// The timeout `next_timeout` currently uses in adaptive mode, once it has one.
+    pub fn adaptive_timeout(&self) -> Option<Duration> {
+        self.adaptive.as_ref().and_then(Adaptive::timeout)
+    }
+
+    /// `next_timeout` with exactly the given timeout, whatever the adaptive mode says.
+    fn next_within(&mut self, timeout: Duration) -> Result<T, error::Error> {
+        self.last_timed_out = false;
+        if self.adaptive.is_some() && self.buffer.is_empty() {
+            // Already queued, so it says nothing about the gap to the next arrival either
+            self.buffer_ready(1);
+            if !self.buffer.is_empty() {
+                self.adaptive.as_mut().unwrap().last_arrival = None;
+            }
+        }
+        if let Some(item) = self.pop_front() {
+            return Ok(item);
+        };
+
+        let arrival = self.receive_or_heartbeat(Some(timeout));
+        self.last_timed_out = matches!(arrival, Err(error::Error::TimedOut));
+        match arrival? {
+            Arrival::Item(item) => {
+                self.buffer_ready(self.batch_size);
+                self.arrived();
+                Ok(self.record(item))
+            }
+            Arrival::Heartbeat(item) => Ok(item),
+        }
+    }
+
+    fn arrived(&mut self) {
+        if let Some(adaptive) = &mut self.adaptive {
+            adaptive.arrived(self.clock.now());
+        }
+    }
+
+    /// `next_timeout` that also gives up with `ConditionMet` as soon as `cond` holds, for
+    /// waking on some external event. `cond` is checked before waiting and then once every
+    /// tick (see `set_tick`), so it should be cheap.
+    pub fn next_timeout_or(
+        &mut self,
+        timeout: Duration,
+        cond: impl Fn() -> bool,
+    ) -> Result<T, error::Error> {
+        let deadline = self.clock.now() + timeout;
+        loop {
+            if cond() {
+                return Err(error::Error::ConditionMet);
+            }
+
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            match self.next_within(remaining.min(self.tick)) {
+                Err(error::Error::TimedOut) if remaining > self.tick => {}
+                result => return result,
+            }
+        }
+    }
+
+    /// Whether the most recent `next_timeout` or `peek_timeout` failed with `TimedOut`, for
+    /// adjusting a polling backoff without keeping track of the results. Helpers built on
+    /// `next_timeout`, such as `nth_timeout`, update it too.
+    pub fn last_timed_out(&self) -> bool {
+        self.last_timed_out
+    }
+
+    /// `next_timeout` when given a timeout, and a wait for as long as it takes when given
+    /// `None`, for code that handles both alike.
+    pub fn next_timeout_opt_duration(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<T, error::Error> {
+        match timeout {
+            Some(timeout) => self.next_timeout(timeout),
+            None => self.next_blocking(),
+        }
+    }
+
+    /// `next_timeout` with a timeout turned into `T::default()`, for pipelines that would
+    /// rather carry on with a placeholder. Other errors, such as `Disconnected`, still fail.
+    pub fn next_timeout_or_default(&mut self, timeout: Duration) -> Result<T, error::Error>
+    where
+        T: Default,
+    {
+        match self.next_timeout(timeout) {
+            Err(error::Error::TimedOut) => Ok(T::default()),
+            result => result,
+        }
+    }
+
+    /// `next_timeout` with its error converted by `map`, for callers with an error type of
+    /// their own.
+    pub fn next_timeout_map_err<E>(
+        &mut self,
+        timeout: Duration,
+        map: impl FnOnce(error::Error) -> E,
+    ) -> Result<T, E> {
+        self.next_timeout(timeout).map_err(map)
+    }
+
+    /// `next_timeout` with the end of the source as `Ok(None)` rather than an error, leaving
+    /// errors for the waits that actually failed, such as `TimedOut`.
+    pub fn next_timeout_opt(&mut self, timeout: Duration) -> Result<Option<T>, error::Error> {
+        match self.next_timeout(timeout) {
+            Ok(item) => Ok(Some(item)),
+            Err(error::Error::Disconnected) => Ok(None),
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Marks the current position so that consumption can be undone with `rewind`, as in
+    /// speculative parsing.
+    ///
+    /// From here on every consumed item is cloned and kept until the checkpoint is rewound or
+    /// committed, so memory grows with the number of items read under a checkpoint. Holding
+    /// one across a long-running stream amounts to keeping the whole stream in memory.
+    /// Checkpoints may be nested, and are released innermost first.
+    pub fn checkpoint(&mut self) -> Checkpoint
+    where
+        T: Clone,
+    {
+        let recording = self.recording.get_or_insert_with(|| Recording {
+            consumed: Vec::new(),
+            clone: T::clone,
+            outstanding: 0,
+        });
+        recording.outstanding += 1;
+
+        Checkpoint {
+            position: recording.consumed.len(),
+        }
+    }
+
+    /// Puts every item consumed since `checkpoint` back in front of the buffer, in order, so
+    /// they are read again.
+    pub fn rewind(&mut self, checkpoint: Checkpoint) {
+        if let Some(mut recording) = self.recording.take() {
+            let position = checkpoint.position.min(recording.consumed.len());
+            let replay = recording.consumed.split_off(position);
+            self.returned(replay.len());
+            for item in replay.into_iter().rev() {
+                self.buffer.push_front(item);
+            }
+            self.release(recording);
+        }
+    }
+
+    /// Keeps everything consumed since `checkpoint`, ending its recording.
+    pub fn commit(&mut self, _checkpoint: Checkpoint) {
+        if let Some(recording) = self.recording.take() {
+            self.release(recording);
+        }
+    }
+
+    fn release(&mut self, mut recording: Recording<T>) {
+        recording.outstanding -= 1;
+        if recording.outstanding > 0 {
+            self.recording = Some(recording);
+        }
+    }
+
+    /// Puts `items` in front of everything else, in order, so they are handed out before
+    /// anything buffered or still to come from the source. Handy for replaying a captured
+    /// prefix.
+    pub fn prepend(&mut self, items: impl IntoIterator<Item = T>) {
+        let items: Vec<T> = items.into_iter().collect();
+        self.returned(items.len());
+        for item in items.into_iter().rev() {
+            self.buffer.push_front(item);
+        }
+    }
+
+    /// Counts `n` items put in front of the buffer as still to consume.
+    fn returned(&mut self, n: usize) {
+        if let Some(remaining) = &mut self.remaining {
+            *remaining += n;
+        }
+        self.status.returned(n);
+    }
+
+    /// A plain iterator over the remaining items, buffered ones first, for code that has no
+    /// use for timeouts. It blocks for each item, with any heartbeat dropped, and ends when
+    /// the source does.
+    pub fn into_blocking(mut self) -> impl Iterator<Item = T> {
+        self.heartbeat = None;
+        std::iter::from_fn(move || self.next_blocking().ok())
+    }
+
+    /// Consumes exactly the items that are buffered right now, without touching the channel,
+    /// so it never blocks. Items the returned iterator doesn't get to stay buffered.
+    pub fn drain(&mut self) -> impl Iterator<Item = T> + '_ {
+        let buffered = self.buffer.len();
+        (0..buffered).map_while(move |_| self.pop_front())
+    }
+
+    /// Like `mpsc::Receiver::try_iter`: consumes the buffered items, then whatever the channel
+    /// can hand over without blocking, stopping as soon as it is momentarily empty.
+    pub fn try_iter(&mut self) -> impl Iterator<Item = T> + '_ {
+        std::iter::from_fn(move || match self.pop_front() {
+            Some(item) => Some(item),
+            None => loop {
+                if let Message::Item(item) = self.source.try_recv().ok()? {
+                    self.observed.received.fetch_add(1, Ordering::AcqRel);
+                    return Some(self.record(item));
+                }
+            },
+        })
+    }
+
+    /// How many items can be had right now without blocking. Anything waiting in the channel
+    /// is moved into the buffer to count it, which keeps the order for later reads.
+    pub fn available(&mut self) -> usize {
+        self.buffer_ready(usize::MAX);
+        self.buffer.len()
+    }
+
+    /// Removes the first buffered item, as consumption.
+    fn pop_front(&mut self) -> Option<T> {
+        let item = self.buffer.pop_front()?;
+        Some(self.record(item))
+    }
+
+    /// Keeps a copy of a consumed item for any outstanding checkpoint.
+    fn record(&mut self, item: T) -> T {
+        self.status.consumed();
+        if let Some(remaining) = &mut self.remaining {
+            *remaining = remaining.saturating_sub(1);
+        }
+        if let Some(recording) = &mut self.recording {
+            recording.consumed.push((recording.clone)(&item));
+        }
+        item
+    }
+
+    /// Feeds every item to `f` until the source ends, which counts as success. A gap between
+    /// items longer than `timeout` stops early with `Err(TimedOut)`.
+    pub fn for_each_timeout(
+        mut self,
+        timeout: Duration,
+        mut f: impl FnMut(T),
+    ) -> Result<(), error::Error> {
+        loop {
+            match self.next_timeout(timeout) {
+                Ok(item) => f(item),
+                Err(error::Error::Disconnected) => return Ok(()),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Folds every item into an accumulator until the source ends. A gap between items longer
+    /// than `timeout` returns `Err(TimedOut)` and the partial accumulator is discarded.
+    pub fn fold_timeout<B>(
+        mut self,
+        init: B,
+        timeout: Duration,
+        mut f: impl FnMut(B, T) -> B,
+    ) -> Result<B, error::Error> {
+        let mut accumulator = init;
+        loop {
+            match self.next_timeout(timeout) {
+                Ok(item) => accumulator = f(accumulator, item),
+                Err(error::Error::Disconnected) => return Ok(accumulator),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Maps items through `f` until it returns `None`, like `Iterator::map_while`. The item
+    /// that `f` rejected is consumed. The end of the source also ends the run. `timeout` is
+    /// shared by the whole run; if it elapses first the result is `Err(TimedOut)`, and the
+    /// items mapped so far are lost.
+    pub fn map_while_timeout<U>(
+        &mut self,
+        timeout: Duration,
+        mut f: impl FnMut(T) -> Option<U>,
+    ) -> Result<Vec<U>, error::Error> {
+        let deadline = self.clock.now() + timeout;
+        let mut mapped = Vec::new();
+        loop {
+            match self.next_before(deadline) {
+                Ok(item) => match f(item) {
+                    Some(value) => mapped.push(value),
+                    None => return Ok(mapped),
+                },
+                Err(error::Error::Disconnected) => return Ok(mapped),
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Collects every item that arrives before `deadline`, as measured by the clock. The
+    /// source ending and the deadline passing both just end the collection, so either way
+    /// the result is `Ok` with everything gathered so far. Only other failures, such as
+    /// `Cancelled`, are errors, and then the items gathered so far stay buffered.
+    pub fn collect_until_deadline(&mut self, deadline: Instant) -> Result<Vec<T>, error::Error> {
+        // Gathered in the buffer, so that a failure leaves them there
+        match self.fill_before(usize::MAX, deadline) {
+            Ok(()) | Err(error::Error::TimedOut) | Err(error::Error::Disconnected) => {
+                Ok(self.drain().collect())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Counts (and consumes) the items that arrive within a single `window`, stopping early if
+    /// the source ends. Handy for measuring a source's throughput.
+    ///
+    /// A stop from a `StopHandle` or a `Builder::max_buffered` overflow ends the count early
+    /// as well, with no sign of it in the count itself. The next call fails with `Cancelled`
+    /// or `BufferOverflow` then, which tells those apart from a window that closed.
+    pub fn count_timeout(&mut self, window: Duration) -> usize {
+        let deadline = self.clock.now() + window;
+        let mut count = 0;
+        while self.clock.now() < deadline {
+            match self.next_before(deadline) {
+                Ok(_) => count += 1,
+                Err(error::Error::TimedOut) | Err(error::Error::Disconnected) => break,
+                // Cut short, see above: nothing more is coming within the window anyway
+                Err(_) => break,
+            }
+        }
+        count
+    }
+
+    /// Discards `n` items and returns the one after them. `timeout` applies to each item in
+    /// turn rather than to the whole skip, so a steady source never times out however large
+    /// `n` is.
+    pub fn nth_timeout(&mut self, n: usize, timeout: Duration) -> Result<T, error::Error> {
+        for _ in 0..n {
+            self.next_timeout(timeout)?;
+        }
+        self.next_timeout(timeout)
+    }
+
+    /// Consumes exactly `N` items, for fixed-format records, waiting at most `timeout` for all
+    /// of them together. When fewer arrive in time (`TimedOut`), or the source ends short
+    /// (`Disconnected`), nothing is consumed: the items so far stay buffered.
+    pub fn next_array_timeout<const N: usize>(
+        &mut self,
+        timeout: Duration,
+    ) -> Result<[T; N], error::Error> {
+        let deadline = self.clock.now() + timeout;
+        self.fill_before(N, deadline)?;
+        Ok(std::array::from_fn(|_| self.pop_front().unwrap()))
+    }
+
+    /// Consumes every item up to the next one equal to `sentinel`, for protocols framed by
+    /// a marker, waiting at most `timeout` for the whole frame. The sentinel is consumed
+    /// as well, but not returned. When it doesn't arrive in time (`TimedOut`), or the
+    /// source ends first (`Disconnected`), nothing is consumed.
+    pub fn next_until(&mut self, timeout: Duration, sentinel: &T) -> Result<Vec<T>, error::Error>
+    where
+        T: PartialEq,
+    {
+        let deadline = self.clock.now() + timeout;
+        let at = self.buffer_until(deadline, |item| item == sentinel)?;
+
+        let frame = (0..at).map_while(|_| self.pop_front()).collect();
+        self.pop_front();
+        Ok(frame)
+    }
+
+    /// Prefetches until at least `depth` items are buffered, waiting at most `timeout` in
+    /// total, and returns how many are buffered. Running out of time or of source just stops
+    /// the fill short, so the result may be less than `depth`.
+    pub fn fill_to(&mut self, depth: usize, timeout: Duration) -> Result<usize, error::Error> {
+        self.check_lookahead(depth)?;
+        let deadline = self.clock.now() + timeout;
+        match self.fill_before(depth, deadline) {
+            Ok(()) | Err(error::Error::TimedOut) | Err(error::Error::Disconnected) => {
+                Ok(self.buffer.len())
+            }
+            Err(e) => Err(e),
+        }
+    }
+
+    /// Buffers items until the buffer holds at least `depth` of them, giving up at `deadline`.
+    /// Whatever was received before an error stays buffered.
+    fn fill_before(&mut self, depth: usize, deadline: Instant) -> Result<(), error::Error> {
+        while self.buffer.len() < depth {
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            let item = self.receive(Some(remaining))?;
+            self.buffer.push_back(item);
+        }
+        Ok(())
+    }
+
+    /// Buffers items until one satisfies `found`, giving up at `deadline`, and returns its
+    /// position in the buffer. Nothing is consumed, even on an error.
+    fn buffer_until(
+        &mut self,
+        deadline: Instant,
+        mut found: impl FnMut(&T) -> bool,
+    ) -> Result<usize, error::Error> {
+        let mut scanned = 0;
+        loop {
+            if let Some(at) = self.buffer.iter().skip(scanned).position(&mut found) {
+                return Ok(scanned + at);
+            }
+            scanned = self.buffer.len();
+
+            let remaining = deadline.saturating_duration_since(self.clock.now());
+            let item = self.receive(Some(remaining))?;
+            self.buffer.push_back(item);
+        }
+    }
+
+    /// `next_timeout` against a deadline shared by several calls.
+    fn next_before(&mut self, deadline: Instant) -> Result<T, error::Error> {
+        self.next_within(deadline.saturating_duration_since(self.clock.now()))
+    }
+
+    /// The buffer-then-channel order of `next_timeout`, but waiting for as long as it takes.
+    fn next_blocking(&mut self) -> Result<T, error::Error> {
+        if let Some(item) = self.pop_front() {
+            return Ok(item);
+        };
+
+        match self.receive_or_heartbeat(None)? {
+            Arrival::Item(item) => Ok(self.record(item)),
+            Arrival::Heartbeat(item) => Ok(item),
+        }
+    }
+
+    /// Like `receive`, but a heartbeat interval shorter than `timeout` ends the wait early
+    /// with a heartbeat.
+    fn receive_or_heartbeat(
+        &mut self,
+        timeout: Option<Duration>,
+    ) -> Result<Arrival<T>, error::Error> {
+        let interval = match (&self.heartbeat, timeout) {
+            (Some(heartbeat), Some(timeout)) if heartbeat.interval < timeout => heartbeat.interval,
+            (Some(heartbeat), None) => heartbeat.interval,
+            _ => return self.receive(timeout).map(Arrival::Item),
+        };
+
+        let started = self.clock.now();
+        loop {
+            let waited = self.clock.now().saturating_duration_since(started);
+            if waited >= interval {
+                let heartbeat = self.heartbeat.as_ref().unwrap();
+                return Ok(Arrival::Heartbeat((heartbeat.make)()));
+            }
+
+            let mut slice = self.tick;
+            if let Some(timeout) = timeout {
+                if waited >= timeout {
+                    return Err(error::Error::TimedOut);
+                }
+                slice = slice.min(timeout - waited);
+            }
+            match self.receive(Some(slice)) {
+                Err(error::Error::TimedOut) => {}
+                received => return received.map(Arrival::Item),
+            }
+        }
+    }
+
+    /// Takes the next item from the channel, waiting at most `timeout` (or forever when it
+    /// is `None`). Switches over to the fallback source, if any, when the channel disconnects.
+    fn receive(&mut self, timeout: Option<Duration>) -> Result<T, error::Error> {
+        loop {
+            if self.controls.is_stopped() {
+                return Err(error::Error::Cancelled);
+            }
+
+            let received = match timeout {
+                Some(timeout) => self.source.recv_timeout(&self.clock, timeout),
+                None => self
+                    .source
+                    .recv()
+                    .map_err(|_| mpsc::RecvTimeoutError::Disconnected),
+            };
+
+            match received {
+                Ok(Message::Item(item)) => {
+                    self.observed.received.fetch_add(1, Ordering::AcqRel);
+                    return Ok(item);
+                }
+                // Seen as stopped on the next turn
+                Ok(Message::Stop) => {}
+                Err(mpsc::RecvTimeoutError::Timeout) => return Err(error::Error::TimedOut),
+                Err(mpsc::RecvTimeoutError::Disconnected)
+                    if self.status.state() == SenderState::Overflowed =>
+                {
+                    self.observed.disconnected.store(true, Ordering::Release);
+                    return Err(error::Error::BufferOverflow);
+                }
+                Err(mpsc::RecvTimeoutError::Disconnected) => match self.fallback.take() {
+                    Some(fallback) => self.replace_source(fallback)?,
+                    None => {
+                        if self.finished_normally() {
+                            if let Some(make_end) = self.end_marker.take() {
+                                return Ok(make_end());
+                            }
+                        }
+                        self.observed.disconnected.store(true, Ordering::Release);
+                        return Err(error::Error::Disconnected);
+                    }
+                },
+            }
+        }
+    }
+
+    /// Whether the current source ran to its end, rather than panicking or being stopped. A
+    /// producer hanging up on `from_receiver`'s channel is as normal an end as it gets.
+    fn finished_normally(&self) -> bool {
+        match self.source {
+            Source::Sender(_) => self.status.state() == SenderState::Finished,
+            Source::Receiver(_) => !self.controls.is_stopped(),
+        }
+    }
+
+    /// Moves up to `limit` items that are already waiting in the channel into the buffer,
+    /// without blocking.
+    fn buffer_ready(&mut self, limit: usize) {
+        for _ in 0..limit {
+            match self.source.try_recv() {
+                Ok(Message::Item(item)) => {
+                    self.observed.received.fetch_add(1, Ordering::AcqRel);
+                    self.buffer.push_back(item);
+                }
+                Ok(Message::Stop) => {}
+                Err(_) => return,
+            }
+        }
+    }
+
+    /// Returns a sliding window over the next `size` items, then slides it forward by one, so
+    /// successive calls over `[1, 2, 3]` with a size of 2 give `[1, 2]` and then `[2, 3]`.
+    /// `timeout` bounds the whole fill; on failure the partial window stays buffered.
+    pub fn windows_timeout(
+        &mut self,
+        size: usize,
+        timeout: Duration,
+    ) -> Result<Vec<T>, error::Error>
+    where
+        T: Clone,
+    {
+        let deadline = self.clock.now() + timeout;
+        self.fill_before(size, deadline)?;
+
+        let window = self.buffer.iter().take(size).cloned().collect();
+        if size > 0 {
+            self.pop_front();
+        }
+        Ok(window)
+    }
+
+    /// Collects the next run of consecutive items sharing the same `key`, such as log lines
+    /// for one request. The first item with a different key is left buffered for the next
+    /// call. `timeout` bounds the wait for each item: a quiet source ends the run, or fails
+    /// with `TimedOut` when not even the first item arrives.
+    pub fn group_by_timeout<K: PartialEq>(
+        &mut self,
+        key: impl Fn(&T) -> K,
+        timeout: Duration,
+    ) -> Result<Vec<T>, error::Error> {
+        let first = self.next_timeout(timeout)?;
+        let group_key = key(&first);
+        let mut group = vec![first];
+
+        loop {
+            match self.peek_timeout(timeout) {
+                Ok(item) if key(item) == group_key => group.extend(self.pop_front()),
+                Ok(_) | Err(error::Error::TimedOut) | Err(error::Error::Disconnected) => {
+                    return Ok(group)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    /// Takes the next item, then folds in everything else that arrives within `window` of
+    /// it. Bursts of counter updates, say, come out as a single combined value. Waiting for
+    /// the first item is not bounded by `window`.
+    pub fn coalesce_timeout(
+        &mut self,
+        window: Duration,
+        combine: impl Fn(T, T) -> T,
+    ) -> Result<T, error::Error> {
+        let mut combined = self.next_blocking()?;
+
+        let deadline = self.clock.now() + window;
+        loop {
+            match self.next_before(deadline) {
+                Ok(item) => combined = combine(combined, item),
+                Err(error::Error::TimedOut) | Err(error::Error::Disconnected) => {
+                    return Ok(combined)
+                }
+                Err(e) => return Err(e),
+            }
+        }
+    }
+
+    pub fn peek_timeout(&mut self, timeout: Duration) -> Result<&T, error::Error> {
+        self.last_timed_out = false;
+        if self.buffer.is_empty() {
+            let received = self.receive(Some(timeout));
+            self.last_timed_out = matches!(received, Err(error::Error::TimedOut));
+            self.buffer.push_back(received?);
+        };
+
+        Ok(self.buffer.front().unwrap())
+    }
+
+    /// Waits, at most `timeout`, until there is at least one item to consume. Unlike
+    /// `peek_timeout` it holds no borrow, so a loop of `next` or `drain` can follow.
+    pub fn wait_ready(&mut self, timeout: Duration) -> Result<(), error::Error> {
+        self.peek_timeout(timeout).map(|_| ())
+    }
+
+    /// Peeks at the next two items at once, for lookahead that compares them, waiting at
+    /// most `timeout` in total. Both stay unconsumed. A source that ends after a single item
+    /// is `Disconnected`, with that item still buffered.
+    pub fn peek_two(&mut self, timeout: Duration) -> Result<(&T, &T), error::Error> {
+        self.check_lookahead(2)?;
+        let deadline = self.clock.now() + timeout;
+        let filled = self.fill_before(2, deadline);
+        self.last_timed_out = matches!(filled, Err(error::Error::TimedOut));
+        filled?;
+
+        Ok((&self.buffer[0], &self.buffer[1]))
+    }
+
+    /// `peek_timeout` for "latest value" sources such as telemetry: whatever has already
+    /// arrived is taken in, and only the newest item is kept, so the peek is as current as
+    /// possible. The stale items are discarded, as if consumed. Only waits, up to `timeout`,
+    /// when nothing at all is available.
+    pub fn peek_timeout_refresh(&mut self, timeout: Duration) -> Result<&T, error::Error> {
+        self.buffer_ready(usize::MAX);
+        while self.buffer.len() > 1 {
+            self.pop_front();
+        }
+        self.peek_timeout(timeout)
+    }
+
+    /// Consumes the next item only if `pred` holds for it, as `Peekable::next_if` does.
+    /// Otherwise the item stays buffered and the result is `Ok(None)`.
+    pub fn next_if(
+        &mut self,
+        timeout: Duration,
+        pred: impl FnOnce(&T) -> bool,
+    ) -> Result<Option<T>, error::Error> {
+        if pred(self.peek_timeout(timeout)?) {
+            Ok(self.pop_front())
+        } else {
+            Ok(None)
+        }
+    }
+
+    /// `next_if` for the common case of consuming an expected item, such as a token.
+    pub fn next_if_eq(&mut self, timeout: Duration, expected: &T) -> Result<Option<T>, error::Error>
+    where
+        T: PartialEq,
+    {
+        self.next_if(timeout, |item| item == expected)
+    }
+
+    /// Peeks at the run of upcoming items for which `pred` holds, consuming none of them.
+    /// Items are fetched until one fails `pred` (which stays buffered as well), the source
+    /// ends, or `timeout` runs out for the whole call; any of those ends the run.
+    pub fn peek_while(
+        &mut self,
+        timeout: Duration,
+        mut pred: impl FnMut(&T) -> bool,
+    ) -> Result<&[T], error::Error> {
+        let deadline = self.clock.now() + timeout;
+
+        let mut run = 0;
+        loop {
+            if run == self.buffer.len() {
+                self.check_lookahead(run + 1)?;
+                let remaining = deadline.saturating_duration_since(self.clock.now());
+                match self.receive(Some(remaining)) {
+                    Ok(item) => self.buffer.push_back(item),
+                    Err(error::Error::TimedOut) | Err(error::Error::Disconnected) => break,
+                    Err(e) => return Err(e),
+                }
+            }
+            if !pred(&self.buffer[run]) {
+                break;
+            }
+            run += 1;
+        }
+
+        Ok(&self.buffer.make_contiguous()[..run])
+    }
+
+    pub fn peek(&mut self) -> Option<&T> {
+        if self.buffer.is_empty() {
+            // Straight from the channel: going through `next` would count as consumption
+            match self.receive(None) {
+                Ok(item) => self.buffer.push_back(item),
+                Err(_) => {
+                    return None;
+                }
+            }
+        };
+
+        Some(self.buffer.front().unwrap())
+    }
+}
+
+impl<T, C> TimeoutIterator<T, C>
+where
+    T: Send + 'static,
+    C: Clock + Send + 'static,
+{
+    /// Runs a stateful scan, as in `Iterator::scan`, on a background thread, and wraps its
+    /// output in a new `TimeoutIterator`. The output disconnects once `f` returns `None`.
+    pub fn scan_timeout<St, U>(
+        self,
+        init: St,
+        f: impl FnMut(&mut St, T) -> Option<U> + Send + 'static,
+    ) -> Result<TimeoutIterator<U>, error::Error>
+    where
+        St: Send + 'static,
+        U: Send + 'static,
+    {
+        self.builder.clone().with_iter(self.scan(init, f))
+    }
+
+    /// Pairs up items from both iterators on a background thread, so `next_timeout` on the
+    /// result bounds the time to produce a whole pair. Ends as soon as either side ends.
+    pub fn zip_timeout<U, D>(
+        self,
+        other: TimeoutIterator<U, D>,
+    ) -> Result<TimeoutIterator<(T, U)>, error::Error>
+    where
+        U: Send + 'static,
+        D: Clock + Send + 'static,
+    {
+        self.builder.clone().with_iter(self.zip(other))
+    }
+
+    /// Splits the items in two by `pred` on a background thread: the first iterator gets
+    /// those `pred` holds for, the second the rest, each keeping their order. The two are
+    /// independent, so waiting on or neglecting one doesn't block the other, though what the
+    /// neglected side hasn't consumed yet keeps piling up in its channel. Both end when the
+    /// source does.
+    pub fn partition_timeout(
+        self,
+        pred: impl Fn(&T) -> bool + Send + 'static,
+    ) -> Result<(TimeoutIterator<T>, TimeoutIterator<T>), error::Error> {
+        let (matching_sink, matching) = mpsc::channel();
+        let (rest_sink, rest) = mpsc::channel();
+        self.route(pred, (matching_sink, rest_sink), |sink, item| {
+            sink.send(item).is_ok()
+        })?;
+
+        Ok((
+            TimeoutIterator::from_receiver(matching),
+            TimeoutIterator::from_receiver(rest),
+        ))
+    }
+
+    /// `partition_timeout` with room for at most `capacity` unconsumed items on each side.
+    /// This iterator stays paused, with its sender pulling one item from the source at a
+    /// time, only as routing is ready for it. So when either side is full, a slow consumer
+    /// there slows the source itself down rather than letting items pile up, and the other
+    /// side has to wait too. The sides don't batch, so `capacity` is all each one holds, but
+    /// peeking on one still buffers what it looks at. A `from_receiver` source has no sender
+    /// to hold back.
+    pub fn partition_bounded_timeout(
+        self,
+        pred: impl Fn(&T) -> bool + Send + 'static,
+        capacity: usize,
+    ) -> Result<(TimeoutIterator<T>, TimeoutIterator<T>), error::Error> {
+        let (matching_sink, matching) = mpsc::sync_channel(capacity);
+        let (rest_sink, rest) = mpsc::sync_channel(capacity);
+        self.pause();
+        self.route(pred, (matching_sink, rest_sink), |sink, item| {
+            sink.send(item).is_ok()
+        })?;
+
+        let unbatched = |receiver| {
+            let mut side = TimeoutIterator::from_receiver(receiver);
+            side.set_batch_size(0);
+            side
+        };
+        Ok((unbatched(matching), unbatched(rest)))
+    }
+
+    /// Hands each item to the first sink if `pred` holds for it and to the second one
+    /// otherwise, from a background thread. `deliver` sends an item, telling whether that
+    /// side is still there. Before each item the sender is let through once, which only
+    /// makes a difference if this iterator is paused.
+    fn route<S: Send + 'static>(
+        self,
+        pred: impl Fn(&T) -> bool + Send + 'static,
+        (matching, rest): (S, S),
+        deliver: fn(&S, T) -> bool,
+    ) -> Result<(), error::Error> {
+        let builder = self.builder.clone();
+        let mut source = self;
+        builder.spawn_thread(move || {
+            let (mut matching_open, mut rest_open) = (true, true);
+            loop {
+                source.controls.let_one_through();
+                let item = match source.next() {
+                    Some(item) => item,
+                    None => return,
+                };
+                if pred(&item) {
+                    matching_open = deliver(&matching, item);
+                } else {
+                    rest_open = deliver(&rest, item);
+                }
+                // Only worth going on while someone is listening
+                if !matching_open && !rest_open {
+                    return;
+                }
+            }
+        })?;
+        Ok(())
+    }
+
+    /// Tags every item with the `Instant` it was received at, for working out latencies and
+    /// inter-arrival times after the fact. A background thread does the receiving, so the tag
+    /// reflects arrival rather than consumption. Items already buffered are tagged as they
+    /// are handed over, right away.
+    pub fn with_timestamps(self) -> Result<TimeoutIterator<(Instant, T)>, error::Error> {
+        self.builder
+            .clone()
+            .with_iter(self.map(|item| (Instant::now(), item)))
+    }
+}
+
+impl<U, E, C> TimeoutIterator<Result<U, E>, C>
+where
+    U: Send + 'static,
+    E: Send + 'static,
+    C: Clock,
+{
+    /// `next_timeout` for sources of results, such as `BufRead::lines`, with an item's own
+    /// error folded in with the iterator's. One `?` then covers both, also in functions
+    /// returning `io::Result`.
+    pub fn next_timeout_flat(&mut self, timeout: Duration) -> Result<U, error::FlatError<E>> {
+        self.next_timeout(timeout)?.map_err(error::FlatError::Item)
+    }
+}
+
+impl<C: Clock> TimeoutIterator<u8, C> {
+    /// `Read::read`, but waiting at most `timeout` for the first byte. Running out of time
+    /// is `ErrorKind::WouldBlock`, as it is for a socket with a read timeout.
+    pub fn read_timeout(&mut self, buf: &mut [u8], timeout: Duration) -> std::io::Result<usize> {
+        self.read_within(buf, Some(timeout))
+    }
+
+    /// `BufRead::read_line` with a time limit for the whole line: appends the next line,
+    /// newline included, to `buf`. If no complete line arrives within `timeout` the result
+    /// is `ErrorKind::TimedOut` and nothing is consumed, so the bytes of a partial line are
+    /// still there for the next call. At the end of the source it appends any unterminated
+    /// last line, and then reads 0.
+    pub fn read_line_timeout(
+        &mut self,
+        buf: &mut String,
+        timeout: Duration,
+    ) -> std::io::Result<usize> {
+        let deadline = self.clock.now() + timeout;
+        let end = match self.buffer_until(deadline, |byte| *byte == b'\n') {
+            Ok(newline) => newline + 1,
+            Err(error::Error::Disconnected) | Err(error::Error::Cancelled) => self.buffer.len(),
+            Err(error::Error::TimedOut) => return Err(std::io::ErrorKind::TimedOut.into()),
+            Err(e) => return Err(std::io::Error::other(e)),
+        };
+
+        let line: Vec<u8> = (0..end).map_while(|_| self.pop_front()).collect();
+        let line = String::from_utf8(line)
+            .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+        buf.push_str(&line);
+        Ok(end)
+    }
+
+    /// Waits (at most `timeout`, if any) for one byte, then adds whatever is ready without
+    /// blocking for more.
+    fn read_within(&mut self, buf: &mut [u8], timeout: Option<Duration>) -> std::io::Result<usize> {
+        let (first, rest) = match buf.split_first_mut() {
+            Some(split) => split,
+            None => return Ok(0),
+        };
+
+        let received = match timeout {
+            Some(timeout) => self.next_timeout(timeout),
+            None => self.next_blocking(),
+        };
+        *first = match received {
+            Ok(byte) => byte,
+            // The end of the source (or a stop) is the end of the file
+            Err(error::Error::Disconnected) | Err(error::Error::Cancelled) => return Ok(0),
+            Err(error::Error::TimedOut) => return Err(std::io::ErrorKind::WouldBlock.into()),
+            Err(e) => return Err(std::io::Error::other(e)),
+        };
+
+        let mut read = 1;
+        for (slot, byte) in rest.iter_mut().zip(self.try_iter()) {
+            *slot = byte;
+            read += 1;
+        }
+        Ok(read)
+    }
+}
+
+/// Blocks for the first byte of each read, and ends (reads 0) when the source does.
+impl<C: Clock> std::io::Read for TimeoutIterator<u8, C> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.read_within(buf, None)
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T, C> TimeoutIterator<T, C>
+where
+    T: Send + 'static,
+    C: Clock + Send + 'static,
+{
+    /// Bridges into async code by forwarding every remaining item (buffered ones first) to
+    /// a `Stream` from a dedicated thread. The stream needs no particular runtime, and the
+    /// forwarding thread exits once the source ends or the stream is dropped.
+    pub fn into_stream(self) -> Result<BlockingStream<T>, error::Error> {
+        let (sink, items) = futures::channel::mpsc::unbounded();
+        let (finished, closed) = futures::channel::oneshot::channel::<()>();
+
+        thread::Builder::new()
+            .name("TimeoutIterator::stream".to_owned())
+            .spawn(move || {
+                // Dropped however the thread ends, which is what resolves `closed`
+                let _finished = finished;
+                for item in self {
+                    if sink.unbounded_send(item).is_err() {
+                        return;
+                    }
+                }
+            })?;
+
+        Ok(BlockingStream {
+            items,
+            closed: closed.shared(),
+        })
+    }
+}
+
+/// The `Stream` from `TimeoutIterator::into_stream`.
+#[cfg(feature = "async")]
+pub struct BlockingStream<T> {
+    items: futures::channel::mpsc::UnboundedReceiver<T>,
+    closed: futures::future::Shared<futures::channel::oneshot::Receiver<()>>,
+}
+
+#[cfg(feature = "async")]
+impl<T> BlockingStream<T> {
+    /// Resolves once the forwarding thread is done, because the source ended or was stopped
+    /// through a `StopHandle`, for `select!`ing on during shutdown. Items it forwarded may
+    /// still be waiting in the stream.
+    pub fn closed(&self) -> impl std::future::Future<Output = ()> + Send + 'static {
+        self.closed.clone().map(|_| ())
+    }
+}
+
+#[cfg(feature = "async")]
+impl<T> futures::stream::Stream for BlockingStream<T> {
+    type Item = T;
+
+    fn poll_next(
+        mut self: std::pin::Pin<&mut Self>,
+        cx: &mut std::task::Context<'_>,
+    ) -> std::task::Poll<Option<T>> {
+        self.items.poll_next_unpin(cx)
+    }
+}
+
+impl<T, C> Iterator for TimeoutIterator<T, C>
+where
+    T: Send + 'static,
+    C: Clock,
+{
+    type Item = T;
+    /// Blocks until the next item. The end of the source ends the iteration, and so does a
+    /// `StopHandle::stop`, which wakes a `next` that is waiting.
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.next_blocking() {
+            Ok(item) => Some(item),
+            // A deliberate shutdown, nothing to report
+            Err(error::Error::Cancelled) => None,
+            Err(e) => {
+                eprintln!(
+                    "TimeoutIterator:: Error occurred reading from source: {}.",
+                    e
+                );
+                None
+            }
+        }
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        match self.remaining {
+            Some(remaining) if self.heartbeat.is_none() => {
+                let marker = usize::from(self.end_marker.is_some());
+                (remaining, Some(remaining + marker))
+            }
+            _ => (0, None),
+        }
+    }
+}
+
+/// The shape of `std::iter::Peekable` — `next` plus a borrowing `peek` — so that code generic
+/// over it accepts either a `Peekable` or a `TimeoutIterator`.
+pub trait TimeoutPeekable: Iterator {
+    fn peek(&mut self) -> Option<&Self::Item>;
+}
+
+impl<T, C> TimeoutPeekable for TimeoutIterator<T, C>
+where
+    T: Send + 'static,
+    C: Clock,
+{
+    fn peek(&mut self) -> Option<&T> {
+        TimeoutIterator::peek(self)
+    }
+}
+
+impl<I: Iterator> TimeoutPeekable for std::iter::Peekable<I> {
+    fn peek(&mut self) -> Option<&I::Item> {
+        std::iter::Peekable::peek(self)
+    }
+}
+
+#[cfg(all(test, feature = "sync"))]
+mod tests {
+    use super::*;
+    use crate::testing::MockClock;
+    use assert_matches::assert_matches;
+    use std::io::prelude::*;
+
+    /// A source that never produces anything, nor ends.
+    fn silent() -> impl Iterator<Item = u32> + Send + 'static {
+        std::iter::from_fn(|| loop {
+            thread::park();
+        })
+    }
+
+    /// Ends a source, but only after telling `done`, which the sender thread gets to once
+    /// it has handed over every item before.
+    fn signal_end(done: mpsc::Sender<()>) -> impl Iterator<Item = u32> + Send + 'static {
+        std::iter::from_fn(move || {
+            let _ = done.send(());
+            None
+        })
+    }
+
+    #[test]
+    fn iterates() {
+        let realistic_message = r"1
+2
+3
+4
+5";
+        let lines_iterator = (Box::new(realistic_message.as_bytes())).lines();
+
+        let mut ti = TimeoutIterator::with_iter(lines_iterator).unwrap();
+
+        assert_eq!(ti.next().unwrap().unwrap(), "1");
+        assert_eq!(ti.next().unwrap().unwrap(), "2");
+        assert_eq!(ti.next().unwrap().unwrap(), "3");
+        assert_eq!(ti.next().unwrap().unwrap(), "4");
+        assert_eq!(ti.next().unwrap().unwrap(), "5");
+    }
+
+    #[test]
+    fn next_timeout() {
+        let realistic_message = r"1
+2
+3
+4
+5";
+        let lines_iterator = (Box::new(realistic_message.as_bytes())).lines();
+
+        let mut ti = TimeoutIterator::with_iter(lines_iterator).unwrap();
+
+        assert_eq!(ti.next().unwrap().unwrap(), "1");
+        assert_eq!(ti.next().unwrap().unwrap(), "2");
+        assert_eq!(ti.next().unwrap().unwrap(), "3");
+        assert_eq!(ti.next().unwrap().unwrap(), "4");
+        assert_eq!(ti.next().unwrap().unwrap(), "5");
+
+        let timeout_result = ti.next_timeout(Duration::from_secs(1));
+        assert!(timeout_result.is_err());
+    }
+
+    #[test]
+    fn peek_timeout_doesnt_remove() {
+        let realistic_message = r"1
+2
+3
+4
+5";
+        let lines_iterator = (Box::new(realistic_message.as_bytes())).lines();
+        let mut ti = TimeoutIterator::with_iter(lines_iterator).unwrap();
+
+        assert_eq!(ti.next().unwrap().unwrap(), "1");
+        assert_eq!(ti.next().unwrap().unwrap(), "2");
+        assert_eq!(
+            ti.peek_timeout(Duration::from_secs(1))
+                .ok()
+                .unwrap()
+                .as_ref()
+                .unwrap(),
+            "3"
+        );
+        assert_eq!(ti.next().unwrap().unwrap(), "3");
+        assert_eq!(ti.next().unwrap().unwrap(), "4");
+        assert_eq!(
+            ti.peek_timeout(Duration::from_secs(1))
+                .ok()
+                .unwrap()
+                .as_ref()
+                .unwrap(),
+            "5"
+        );
+        assert_eq!(
+            ti.peek_timeout(Duration::from_secs(1))
+                .ok()
+                .unwrap()
+                .as_ref()
+                .unwrap(),
+            "5"
+        );
+        assert_eq!(ti.next().unwrap().unwrap(), "5");
+
+        let timeout_result = ti.next_timeout(Duration::from_secs(1));
+        assert!(timeout_result.is_err());
+    }
+
+    #[test]
+    fn peek_doesnt_remove() {
+        let realistic_message = r"1
+2
+3
+4
+5";
+        let lines_iterator = (Box::new(realistic_message.as_bytes())).lines();
+        let mut ti = TimeoutIterator::with_iter(lines_iterator).unwrap();
+
+        assert_eq!(ti.next().unwrap().unwrap(), "1");
+        assert_eq!(ti.next().unwrap().unwrap(), "2");
+        assert_eq!(ti.peek().unwrap().as_ref().unwrap(), "3");
+        assert_eq!(ti.next().unwrap().unwrap(), "3");
+        assert_eq!(ti.next().unwrap().unwrap(), "4");
+        assert_eq!(ti.peek().unwrap().as_ref().unwrap(), "5");
+        assert_eq!(ti.peek().unwrap().as_ref().unwrap(), "5");
+        assert_eq!(ti.next().unwrap().unwrap(), "5");
+
+        let timeout_result = ti.next_timeout(Duration::from_secs(1));
+        assert!(timeout_result.is_err());
+    }
+
+    #[test]
+    fn item_iterator() {
+        let numbers: Vec<u32> = vec![1, 2, 3, 4, 5];
+        let mut ti = TimeoutIterator::with_iter(numbers.into_iter()).unwrap();
+
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_eq!(ti.next().unwrap(), 2);
+        assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).ok().unwrap(), 3);
+        assert_eq!(ti.next().unwrap(), 3);
+        assert_eq!(ti.next().unwrap(), 4);
+        assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).ok().unwrap(), 5);
+        assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).ok().unwrap(), 5);
+        assert_eq!(ti.next().unwrap(), 5);
+
+        let timeout_result = ti.next_timeout(Duration::from_secs(1));
+        assert!(timeout_result.is_err());
+    }
+
+    #[test]
+    fn is_sendable() {
+        let numbers: Vec<u32> = vec![1, 2, 3, 4, 5];
+        let mut ti = TimeoutIterator::with_iter(numbers.into_iter()).unwrap();
+        thread::spawn(move || {
+            ti.next();
+        });
+    }
+
+    #[test]
+    fn from_buffered_serves_from_memory() {
+        let mut ti = TimeoutIterator::from_buffered(vec![1u32, 2, 3]);
+
+        // A zero timeout can only succeed if nothing has to cross a thread boundary.
+        assert_eq!(ti.next_timeout(Duration::from_secs(0)).unwrap(), 1);
+        assert_eq!(*ti.peek_timeout(Duration::from_secs(0)).unwrap(), 2);
+        assert_eq!(ti.next_timeout(Duration::from_secs(0)).unwrap(), 2);
+        assert_eq!(ti.next().unwrap(), 3);
+
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+        assert!(ti.next().is_none());
+    }
+
+    #[test]
+    fn next_timeout_batches_queued_items() {
+        let (done, enqueued) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter((0..10u32).chain(signal_end(done)))
+            .unwrap()
+            .with_clock(MockClock::new());
+        enqueued.recv().unwrap();
+
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 0);
+        assert_eq!(*ti.buffer, (1..10).collect::<Vec<u32>>());
+        for expected in 1..10 {
+            assert_eq!(ti.next_timeout(Duration::from_secs(0)).unwrap(), expected);
+        }
+
+        let (done, enqueued) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter((0..10u32).chain(signal_end(done)))
+            .unwrap()
+            .with_clock(MockClock::new());
+        ti.set_batch_size(3);
+        enqueued.recv().unwrap();
+
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 0);
+        assert_eq!(*ti.buffer, vec![1, 2, 3]);
+        assert_eq!(ti.collect::<Vec<u32>>(), (1..10).collect::<Vec<u32>>());
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn into_stream_collects() {
+        use futures::stream::StreamExt;
+
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4, 5].into_iter()).unwrap();
+        assert_eq!(*ti.peek().unwrap(), 1);
+
+        let stream = ti.into_stream().unwrap();
+        let items: Vec<u32> = futures::executor::block_on(stream.collect());
+        assert_eq!(items, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn for_each_timeout_until_disconnect() {
+        let ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4, 5].into_iter()).unwrap();
+
+        let mut seen = Vec::new();
+        ti.for_each_timeout(Duration::from_secs(1), |item| seen.push(item))
+            .unwrap();
+        assert_eq!(seen, vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn fold_timeout_sums() {
+        let ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4, 5].into_iter()).unwrap();
+
+        let sum = ti
+            .fold_timeout(0, Duration::from_secs(1), |sum, item| sum + item)
+            .unwrap();
+        assert_eq!(sum, 15);
+    }
+
+    #[test]
+    fn count_timeout_measures_rate() {
+        let clock = MockClock::auto_advancing();
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::from_receiver(source).with_clock(clock.clone());
+        let window = Duration::from_millis(500);
+
+        for n in 0u32..7 {
+            sink.send(n).unwrap();
+        }
+        let started = clock.now();
+        assert_eq!(ti.count_timeout(window), 7);
+        assert_eq!(clock.now() - started, window);
+
+        // What comes after the window counts towards the next one
+        sink.send(7).unwrap();
+        assert_eq!(ti.count_timeout(window), 1);
+
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3].into_iter()).unwrap();
+        assert_eq!(ti.count_timeout(Duration::from_secs(1)), 3);
+    }
+
+    #[test]
+    fn count_timeout_of_throttled_source() {
+        let interval = Duration::from_millis(20);
+        let mut ti = TimeoutIterator::with_throttled_iter(0u32.., interval).unwrap();
+
+        // One right away and then one per interval, short of however late the sender runs
+        let count = ti.count_timeout(Duration::from_millis(300));
+        assert!(count >= 5, "counted {}", count);
+        assert!(count <= 16, "counted {}", count);
+    }
+
+    #[test]
+    fn count_timeout_ends_on_stop() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter()).unwrap();
+        sink.send(1u32).unwrap();
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 1);
+
+        ti.stop_handle().stop();
+        assert_eq!(ti.count_timeout(Duration::from_secs(10)), 0);
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)),
+            Err(error::Error::Cancelled)
+        );
+    }
+
+    #[test]
+    fn nth_timeout_skips() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4, 5].into_iter()).unwrap();
+
+        assert_eq!(ti.nth_timeout(0, Duration::from_secs(1)).unwrap(), 1);
+        assert_eq!(ti.nth_timeout(2, Duration::from_secs(1)).unwrap(), 4);
+        assert_matches!(
+            ti.nth_timeout(3, Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+    }
+
+    #[test]
+    fn mock_clock_times_out_without_sleeping() {
+        let clock = MockClock::new();
+        let mut ti = TimeoutIterator::with_iter(silent())
+            .unwrap()
+            .with_clock(clock.clone());
+
+        let started = Instant::now();
+        let advancer = clock.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            advancer.advance(Duration::from_secs(60));
+        });
+
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(60)).unwrap_err(),
+            error::Error::TimedOut
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        // Nothing is waited for when the clock is not advanced and the timeout is zero
+        assert_matches!(
+            ti.peek_timeout(Duration::from_secs(0)).unwrap_err(),
+            error::Error::TimedOut
+        );
+    }
+
+    #[test]
+    fn mock_clock_still_delivers_items() {
+        let clock = MockClock::new();
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter())
+            .unwrap()
+            .with_clock(clock);
+
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 1);
+        assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).unwrap(), 2);
+        assert_eq!(ti.next().unwrap(), 2);
+    }
+
+    #[test]
+    fn replace_source_after_disconnect() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter()).unwrap();
+
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 1);
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 2);
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+
+        ti.replace_source(vec![3u32, 4].into_iter()).unwrap();
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 3);
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 4);
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+    }
+
+    #[test]
+    fn replace_source_keeps_buffer() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32].into_iter()).unwrap();
+        assert_eq!(*ti.peek().unwrap(), 1);
+
+        ti.replace_source(vec![2u32].into_iter()).unwrap();
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_eq!(ti.next().unwrap(), 2);
+    }
+
+    #[test]
+    fn chain_on_disconnect_continues_with_fallback() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter())
+            .unwrap()
+            .chain_on_disconnect(vec![3u32, 4].into_iter());
+
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_eq!(ti.next().unwrap(), 2);
+        assert_eq!(ti.next().unwrap(), 3);
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 4);
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+    }
+
+    #[test]
+    fn sender_thread_is_named() {
+        let thread_name = || std::iter::once_with(|| thread::current().name().map(str::to_owned));
+
+        let mut ti = TimeoutIterator::with_iter(thread_name()).unwrap();
+        assert_eq!(ti.next().unwrap().unwrap(), "TimeoutIterator::sender");
+
+        let mut ti = TimeoutIterator::with_iter_named(thread_name(), "custom-sender").unwrap();
+        assert_eq!(ti.next().unwrap().unwrap(), "custom-sender");
+
+        let mut ti = Builder::new()
+            .name("big-stack")
+            .stack_size(4 * 1024 * 1024)
+            .with_iter(thread_name())
+            .unwrap();
+        assert_eq!(ti.next().unwrap().unwrap(), "big-stack");
+
+        // Later sources keep the configuration
+        ti.replace_source(thread_name()).unwrap();
+        assert_eq!(ti.next().unwrap().unwrap(), "big-stack");
+    }
+
+    #[test]
+    fn pooled_iterators() {
+        let pool = SenderPool::new(2).unwrap();
+        let builder = Builder::new().pool(pool);
+
+        let mut iterators: Vec<TimeoutIterator<u32>> = (0..100)
+            .map(|offset| builder.clone().with_iter(offset..offset + 10).unwrap())
+            .collect();
+
+        for (offset, ti) in iterators.iter_mut().enumerate() {
+            let offset = offset as u32;
+            for expected in offset..offset + 10 {
+                assert_eq!(ti.next_timeout(Duration::from_secs(5)).unwrap(), expected);
+            }
+            assert_matches!(
+                ti.next_timeout(Duration::from_secs(5)).unwrap_err(),
+                error::Error::Disconnected
+            );
+        }
+    }
+
+    #[test]
+    fn pool_survives_panicking_source() {
+        let pool = SenderPool::new(1).unwrap();
+        let builder = Builder::new().pool(pool);
+
+        let panicking = (0u32..).map(|n| if n == 1 { panic!("source failed") } else { n });
+        let mut broken = builder.clone().with_iter(panicking).unwrap();
+        assert_eq!(broken.next_timeout(Duration::from_secs(5)).unwrap(), 0);
+        assert_matches!(
+            broken.next_timeout(Duration::from_secs(5)),
+            Err(error::Error::Disconnected)
+        );
+        assert_eq!(broken.sender_status().state(), SenderState::Panicked);
+
+        // The one worker is still there for the next source
+        let healthy = builder.with_iter(0u32..3).unwrap();
+        assert_eq!(healthy.collect::<Vec<_>>(), vec![0, 1, 2]);
+    }
+
+    #[test]
+    fn pool_needs_a_worker() {
+        assert_matches!(
+            SenderPool::new(0),
+            Err(error::Error::ErrorSpawningThread(_))
+        );
+    }
+
+    #[test]
+    fn windows_timeout_slides() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4].into_iter()).unwrap();
+
+        assert_eq!(
+            ti.windows_timeout(2, Duration::from_secs(1)).unwrap(),
+            vec![1, 2]
+        );
+        assert_eq!(
+            ti.windows_timeout(2, Duration::from_secs(1)).unwrap(),
+            vec![2, 3]
+        );
+        assert_eq!(
+            ti.windows_timeout(2, Duration::from_secs(1)).unwrap(),
+            vec![3, 4]
+        );
+        assert_matches!(
+            ti.windows_timeout(2, Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+        // The incomplete window is kept
+        assert_eq!(ti.next().unwrap(), 4);
+    }
+
+    #[test]
+    fn group_by_timeout_splits_runs() {
+        let mut ti = TimeoutIterator::with_iter(vec!['a', 'a', 'b', 'a'].into_iter()).unwrap();
+        let timeout = Duration::from_millis(100);
+
+        assert_eq!(
+            ti.group_by_timeout(|c| *c, timeout).unwrap(),
+            vec!['a', 'a']
+        );
+        assert_eq!(ti.group_by_timeout(|c| *c, timeout).unwrap(), vec!['b']);
+        assert_eq!(ti.group_by_timeout(|c| *c, timeout).unwrap(), vec!['a']);
+        assert_matches!(
+            ti.group_by_timeout(|c| *c, timeout).unwrap_err(),
+            error::Error::Disconnected
+        );
+    }
+
+    #[test]
+    fn group_by_timeout_ends_run_on_quiet_source() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 1].into_iter().chain(silent())).unwrap();
+        let timeout = Duration::from_millis(100);
+
+        assert_eq!(ti.group_by_timeout(|n| *n, timeout).unwrap(), vec![1, 1]);
+        assert_matches!(
+            ti.group_by_timeout(|n| *n, timeout).unwrap_err(),
+            error::Error::TimedOut
+        );
+    }
+
+    #[test]
+    fn coalesce_timeout_folds_bursts() {
+        let burst_then_solitary = vec![1u32, 2, 3]
+            .into_iter()
+            .chain(std::iter::once_with(|| {
+                thread::sleep(Duration::from_millis(300));
+                10
+            }))
+            .chain(silent());
+        let mut ti = TimeoutIterator::with_iter(burst_then_solitary).unwrap();
+
+        let window = Duration::from_millis(100);
+        assert_eq!(ti.coalesce_timeout(window, |a, b| a + b).unwrap(), 6);
+        assert_eq!(ti.coalesce_timeout(window, |a, b| a + b).unwrap(), 10);
+    }
+
+    #[test]
+    fn checkpoint_and_rewind() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4, 5].into_iter()).unwrap();
+        assert_eq!(ti.next().unwrap(), 1);
+
+        let checkpoint = ti.checkpoint();
+        assert_eq!(ti.next().unwrap(), 2);
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 3);
+        assert_eq!(ti.next().unwrap(), 4);
+        ti.rewind(checkpoint);
+
+        assert_eq!(ti.next().unwrap(), 2);
+        assert_eq!(ti.next().unwrap(), 3);
+        assert_eq!(ti.next().unwrap(), 4);
+        assert_eq!(ti.next().unwrap(), 5);
+        assert!(ti.recording.is_none());
+    }
+
+    #[test]
+    fn nested_checkpoints() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4].into_iter()).unwrap();
+
+        let outer = ti.checkpoint();
+        assert_eq!(ti.next().unwrap(), 1);
+        let inner = ti.checkpoint();
+        assert_eq!(ti.next().unwrap(), 2);
+        ti.rewind(inner);
+        assert_eq!(ti.next().unwrap(), 2);
+        assert_eq!(ti.next().unwrap(), 3);
+        ti.rewind(outer);
+
+        assert_eq!(ti.next().unwrap(), 1);
+        let committed = ti.checkpoint();
+        assert_eq!(ti.next().unwrap(), 2);
+        ti.commit(committed);
+        assert!(ti.recording.is_none());
+        assert_eq!(ti.next().unwrap(), 3);
+    }
+
+    #[test]
+    fn sender_status_reports_why_it_stopped() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32].into_iter()).unwrap();
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+        assert_eq!(ti.sender_status().state(), SenderState::Finished);
+
+        let panicking = vec![1u32]
+            .into_iter()
+            .chain(std::iter::from_fn(|| panic!("boom")));
+        let mut ti = TimeoutIterator::with_iter(panicking).unwrap();
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+        assert_eq!(ti.sender_status().state(), SenderState::Panicked);
+    }
+
+    #[test]
+    fn sender_status_after_consumer_dropped() {
+        let endless = (0u32..).inspect(|_| thread::sleep(Duration::from_millis(10)));
+        let mut ti = TimeoutIterator::with_iter(endless).unwrap();
+        let status = ti.sender_status();
+        assert_eq!(status.state(), SenderState::Running);
+
+        thread::spawn(move || {
+            assert_eq!(ti.next().unwrap(), 0);
+            drop(ti);
+        })
+        .join()
+        .unwrap();
+
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while status.state() == SenderState::Running && Instant::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(status.state(), SenderState::ReceiverDropped);
+    }
+
+    #[test]
+    fn on_drop_flush_receives_buffered_items() {
+        let mut ti =
+            TimeoutIterator::with_iter(vec![1u32, 2, 3].into_iter().chain(silent())).unwrap();
+        assert_eq!(ti.next().unwrap(), 1);
+
+        // Peek two items ahead
+        ti.fill_before(2, Instant::now() + Duration::from_secs(1))
+            .unwrap();
+        assert_eq!(*ti.peek().unwrap(), 2);
+
+        let flushed = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = flushed.clone();
+        ti.on_drop_flush(move |item| sink.lock().unwrap().push(item));
+        drop(ti);
+
+        assert_eq!(*flushed.lock().unwrap(), vec![2, 3]);
+    }
+
+    #[test]
+    fn drain_takes_only_buffered_items() {
+        let mut ti =
+            TimeoutIterator::with_iter(vec![1u32, 2, 3].into_iter().chain(silent())).unwrap();
+        ti.fill_before(3, Instant::now() + Duration::from_secs(1))
+            .unwrap();
+
+        assert_eq!(ti.drain().collect::<Vec<u32>>(), vec![1, 2, 3]);
+        assert!(ti.buffer.is_empty());
+        assert_eq!(ti.drain().count(), 0);
+        assert_matches!(
+            ti.next_timeout(Duration::from_millis(100)).unwrap_err(),
+            error::Error::TimedOut
+        );
+    }
+
+    #[test]
+    fn try_iter_takes_ready_items() {
+        let (done, enqueued) = mpsc::channel();
+        let source = (1u32..=5).chain(signal_end(done)).chain(silent());
+        let mut ti = TimeoutIterator::with_iter(source).unwrap();
+        assert_eq!(*ti.peek().unwrap(), 1);
+        enqueued.recv().unwrap();
+
+        assert_eq!(ti.try_iter().collect::<Vec<u32>>(), vec![1, 2, 3, 4, 5]);
+        assert_eq!(ti.try_iter().count(), 0);
+    }
+
+    #[test]
+    fn available_counts_ready_items() {
+        let (done, enqueued) = mpsc::channel();
+        let source = (1u32..=10).chain(signal_end(done)).chain(silent());
+        let mut ti = TimeoutIterator::with_iter(source).unwrap();
+        enqueued.recv().unwrap();
+
+        assert_eq!(ti.available(), 10);
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_eq!(ti.available(), 9);
+        assert_eq!(
+            ti.try_iter().collect::<Vec<u32>>(),
+            (2..=10).collect::<Vec<u32>>()
+        );
+        assert_eq!(ti.available(), 0);
+    }
+
+    #[test]
+    fn map_while_timeout_stops_at_none() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 30, 4].into_iter()).unwrap();
+
+        let small = ti
+            .map_while_timeout(Duration::from_secs(1), |n| {
+                if n < 10 {
+                    Some(n * 2)
+                } else {
+                    None
+                }
+            })
+            .unwrap();
+        assert_eq!(small, vec![2, 4]);
+        // The rejected item was consumed
+        assert_eq!(ti.next().unwrap(), 4);
+    }
+
+    #[test]
+    fn map_while_timeout_times_out() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter().chain(silent())).unwrap();
+
+        assert_matches!(
+            ti.map_while_timeout(Duration::from_millis(100), Some)
+                .unwrap_err(),
+            error::Error::TimedOut
+        );
+    }
+
+    #[test]
+    fn scan_timeout_running_sum() {
+        let ti = TimeoutIterator::with_iter(1u32..).unwrap();
+
+        let mut sums = ti
+            .scan_timeout(0, |sum, n| {
+                *sum += n;
+                if *sum > 10 {
+                    None
+                } else {
+                    Some(*sum)
+                }
+            })
+            .unwrap();
+
+        assert_eq!(sums.next_timeout(Duration::from_secs(1)).unwrap(), 1);
+        assert_eq!(sums.next_timeout(Duration::from_secs(1)).unwrap(), 3);
+        assert_eq!(sums.next_timeout(Duration::from_secs(1)).unwrap(), 6);
+        assert_eq!(sums.next_timeout(Duration::from_secs(1)).unwrap(), 10);
+        assert_matches!(
+            sums.next_timeout(Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+    }
+
+    #[test]
+    fn zip_timeout_pairs() {
+        let numbers = TimeoutIterator::with_iter(vec![1u32, 2, 3].into_iter()).unwrap();
+        let letters = TimeoutIterator::with_iter(vec!['a', 'b'].into_iter()).unwrap();
+
+        let mut pairs = numbers.zip_timeout(letters).unwrap();
+        assert_eq!(
+            pairs.next_timeout(Duration::from_secs(1)).unwrap(),
+            (1, 'a')
+        );
+        assert_eq!(
+            pairs.next_timeout(Duration::from_secs(1)).unwrap(),
+            (2, 'b')
+        );
+        assert_matches!(
+            pairs.next_timeout(Duration::from_secs(1)).unwrap_err(),
+            error::Error::Disconnected
+        );
+    }
+
+    #[test]
+    fn blocking_peek_after_timed_out_peek() {
+        let slow = vec![1u32, 2]
+            .into_iter()
+            .inspect(|_| thread::sleep(Duration::from_millis(200)));
+        let mut ti = TimeoutIterator::with_iter(slow).unwrap();
+
+        assert_matches!(
+            ti.peek_timeout(Duration::from_millis(50)).unwrap_err(),
+            error::Error::TimedOut
+        );
+        assert!(ti.buffer.is_empty());
+        assert_eq!(*ti.peek().unwrap(), 1);
+        assert_eq!(*ti.peek_timeout(Duration::from_millis(50)).unwrap(), 1);
+        assert_eq!(ti.next().unwrap(), 1);
+
+        assert_matches!(
+            ti.peek_timeout(Duration::from_millis(50)).unwrap_err(),
+            error::Error::TimedOut
+        );
+        assert_eq!(*ti.peek().unwrap(), 2);
+        assert_eq!(ti.next().unwrap(), 2);
+        assert!(ti.peek().is_none());
+    }
+
+    #[test]
+    fn peek_is_not_recorded_as_consumed() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter()).unwrap();
+
+        let checkpoint = ti.checkpoint();
+        assert_eq!(*ti.peek().unwrap(), 1);
+        assert_eq!(ti.next().unwrap(), 1);
+        ti.rewind(checkpoint);
+
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_eq!(ti.next().unwrap(), 2);
+        assert!(ti.next().is_none());
+    }
+
+    #[test]
+    fn peek_and_next_agree_with_plain_iteration() {
+        let mut ti = TimeoutIterator::with_iter(0u32..1000).unwrap();
+
+        for expected in 0u32..1000 {
+            match expected % 3 {
+                0 => assert_eq!(*ti.peek().unwrap(), expected),
+                1 => assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).unwrap(), expected),
+                _ => {}
+            }
+            assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), expected);
+        }
+        assert!(ti.peek().is_none());
+    }
+
+    #[test]
+    fn is_done_after_draining() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter()).unwrap();
+        assert!(!ti.is_done());
+
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_eq!(ti.next().unwrap(), 2);
+        assert!(!ti.is_done());
+
+        assert!(ti.next().is_none());
+        assert!(ti.is_done());
+
+        ti.replace_source(vec![3u32].into_iter()).unwrap();
+        assert!(!ti.is_done());
+    }
+
+    #[test]
+    fn usable_where_peekable_is_expected() {
+        fn leading_small<P: TimeoutPeekable<Item = u32>>(items: &mut P) -> Vec<u32> {
+            let mut small = Vec::new();
+            while let Some(true) = items.peek().map(|n| *n < 3) {
+                small.extend(items.next());
+            }
+            small
+        }
+
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4].into_iter()).unwrap();
+        assert_eq!(leading_small(&mut ti), vec![1, 2]);
+        assert_eq!(ti.next().unwrap(), 3);
+
+        let mut peekable = vec![1u32, 2, 3, 4].into_iter().peekable();
+        assert_eq!(leading_small(&mut peekable), vec![1, 2]);
+    }
+
+    #[test]
+    fn stop_handle_cancels_blocked_wait() {
+        let mut ti = TimeoutIterator::with_iter(silent()).unwrap();
+        let stop = ti.stop_handle();
+
+        let stopper = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            stop.stop();
+        });
+
+        let started = Instant::now();
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(10)),
+            Err(error::Error::Cancelled)
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        stopper.join().unwrap();
+
+        assert!(ti.stop_handle().is_stopped());
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(10)),
+            Err(error::Error::Cancelled)
+        );
+    }
+
+    #[test]
+    fn fill_to_prefetches() {
+        let mut ti = TimeoutIterator::with_iter(1u32..=5).unwrap();
+        ti.set_batch_size(0);
+        assert_eq!(ti.fill_to(3, Duration::from_secs(1)).unwrap(), 3);
+        assert_eq!(*ti.buffer, [1, 2, 3]);
+
+        assert_eq!(ti.next().unwrap(), 1);
+        assert_eq!(ti.fill_to(10, Duration::from_millis(100)).unwrap(), 4);
+    }
+
+    #[test]
+    fn next_timeout_opt_separates_end_from_timeout() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32].into_iter()).unwrap();
+        assert_eq!(
+            ti.next_timeout_opt(Duration::from_secs(1)).unwrap(),
+            Some(1)
+        );
+        assert_eq!(ti.next_timeout_opt(Duration::from_secs(1)).unwrap(), None);
+
+        let mut quiet = TimeoutIterator::with_iter(silent()).unwrap();
+        assert_matches!(
+            quiet.next_timeout_opt(Duration::from_millis(50)),
+            Err(error::Error::TimedOut)
+        );
+    }
+
+    #[test]
+    fn collect_until_deadline_of_finite_source() {
+        let mut ti = TimeoutIterator::with_iter(1u32..=4).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        assert_eq!(
+            ti.collect_until_deadline(deadline).unwrap(),
+            vec![1, 2, 3, 4]
+        );
+        assert!(Instant::now() < deadline);
+    }
+
+    #[test]
+    fn collect_until_deadline_of_endless_source() {
+        let clock = MockClock::new();
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter())
+            .unwrap()
+            .with_clock(clock.clone());
+        sink.send(1u32).unwrap();
+        sink.send(2).unwrap();
+
+        let deadline = clock.now() + Duration::from_secs(1);
+        let advancer = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(50));
+            clock.advance(Duration::from_secs(1));
+        });
+        assert_eq!(ti.collect_until_deadline(deadline).unwrap(), vec![1, 2]);
+        advancer.join().unwrap();
+    }
+
+    #[test]
+    fn heartbeats_while_source_is_quiet() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter())
+            .unwrap()
+            .with_heartbeat(Duration::from_millis(50), || 0u32);
+
+        let started = Instant::now();
+        assert_eq!(ti.next().unwrap(), 0);
+        assert_eq!(ti.next().unwrap(), 0);
+        assert!(started.elapsed() >= Duration::from_millis(100));
+
+        sink.send(7).unwrap();
+        assert_eq!(ti.next().unwrap(), 7);
+
+        // A shorter timeout of its own still wins
+        assert_matches!(
+            ti.next_timeout(Duration::from_millis(10)),
+            Err(error::Error::TimedOut)
+        );
+        assert_eq!(ti.next_timeout(Duration::from_secs(5)).unwrap(), 0);
+    }
+
+    #[test]
+    fn next_timeout_map_err_converts() {
+        #[derive(Debug, PartialEq)]
+        enum ParseError {
+            Stalled,
+            Other,
+        }
+
+        let mut ti = TimeoutIterator::with_iter(silent()).unwrap();
+        let result = ti.next_timeout_map_err(Duration::from_millis(20), |e| match e {
+            error::Error::TimedOut => ParseError::Stalled,
+            _ => ParseError::Other,
+        });
+        assert_eq!(result, Err(ParseError::Stalled));
+    }
+
+    #[test]
+    fn stop_handle_ends_blocking_iteration() {
+        let mut ti = TimeoutIterator::with_iter(silent()).unwrap();
+        let stop = ti.stop_handle();
+
+        let consumer = thread::spawn(move || ti.next());
+        thread::sleep(Duration::from_millis(50));
+        stop.stop();
+
+        assert_eq!(consumer.join().unwrap(), None);
+    }
+
+    #[test]
+    fn last_timed_out_tracks_latest_call() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter()).unwrap();
+        assert!(!ti.last_timed_out());
+
+        assert!(ti.next_timeout(Duration::from_millis(20)).is_err());
+        assert!(ti.last_timed_out());
+
+        sink.send(1u32).unwrap();
+        assert_eq!(*ti.peek_timeout(Duration::from_secs(1)).unwrap(), 1);
+        assert!(!ti.last_timed_out());
+
+        assert!(ti.peek_timeout(Duration::from_millis(20)).is_ok());
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 1);
+        assert!(!ti.last_timed_out());
+    }
+
+    #[test]
+    fn with_timestamps_tags_arrivals() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter())
+            .unwrap()
+            .with_timestamps()
+            .unwrap();
+
+        let mut previous = None;
+        for n in 1u32..=3 {
+            let sent = Instant::now();
+            sink.send(n).unwrap();
+            // Stamped by the time it can be peeked, and not again when consumed
+            let (stamp, item) = *ti.peek().unwrap();
+            let peeked = Instant::now();
+            assert_eq!(item, n);
+            assert!(sent <= stamp && stamp <= peeked);
+            assert!(previous <= Some(stamp));
+            assert_eq!(ti.next().unwrap(), (stamp, n));
+            previous = Some(stamp);
+        }
+    }
+
+    #[test]
+    fn next_timeout_or_wakes_on_condition() {
+        let mut ti = TimeoutIterator::with_iter(silent()).unwrap();
+        let flag = Arc::new(AtomicBool::new(false));
+
+        let flipper = {
+            let flag = flag.clone();
+            thread::spawn(move || {
+                thread::sleep(Duration::from_millis(50));
+                flag.store(true, Ordering::Release);
+            })
+        };
+
+        let started = Instant::now();
+        assert_matches!(
+            ti.next_timeout_or(Duration::from_secs(10), || flag.load(Ordering::Acquire)),
+            Err(error::Error::ConditionMet)
+        );
+        assert!(started.elapsed() < Duration::from_secs(5));
+        flipper.join().unwrap();
+
+        let mut items = TimeoutIterator::with_iter(vec![1u32].into_iter()).unwrap();
+        assert_eq!(
+            items
+                .next_timeout_or(Duration::from_secs(1), || false)
+                .unwrap(),
+            1
+        );
+    }
+
+    #[test]
+    fn max_buffered_stops_runaway_producer() {
+        let mut ti = Builder::new().max_buffered(100).with_iter(0u32..).unwrap();
+        let status = ti.sender_status();
+        while status.state() == SenderState::Running {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(status.state(), SenderState::Overflowed);
+
+        let timeout = Duration::from_secs(1);
+        for expected in 0..100 {
+            assert_eq!(ti.next_timeout(timeout).unwrap(), expected);
+        }
+        assert_matches!(ti.next_timeout(timeout), Err(error::Error::BufferOverflow));
+    }
+
+    #[test]
+    fn remaining_hint_counts_down() {
+        let mut ti = TimeoutIterator::with_exact_iter(vec![1u32, 2, 3].into_iter()).unwrap();
+        assert_eq!(ti.remaining_hint(), Some(3));
+        assert_eq!(ti.size_hint(), (3, Some(3)));
+
+        assert_eq!(*ti.peek().unwrap(), 1);
+        assert_eq!(ti.remaining_hint(), Some(3));
+        ti.next();
+        assert_eq!(ti.remaining_hint(), Some(2));
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 2);
+        assert_eq!(ti.size_hint(), (1, Some(1)));
+        ti.next();
+        assert_eq!(ti.remaining_hint(), Some(0));
+
+        let unknown = TimeoutIterator::with_iter(vec![1u32].into_iter()).unwrap();
+        assert_eq!(unknown.remaining_hint(), None);
+    }
+
+    #[test]
+    fn peek_while_leaves_run_unconsumed() {
+        let mut ti = TimeoutIterator::with_iter(vec![2u32, 4, 6, 7, 8].into_iter()).unwrap();
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(ti.peek_while(timeout, |n| n % 2 == 0).unwrap(), [2, 4, 6]);
+        assert_eq!(ti.next().unwrap(), 2);
+        assert_eq!(ti.peek_while(timeout, |n| n % 2 == 0).unwrap(), [4, 6]);
+        assert_eq!(ti.by_ref().take(3).collect::<Vec<_>>(), vec![4, 6, 7]);
+
+        // The end of the source ends the run too
+        assert_eq!(ti.peek_while(timeout, |n| n % 2 == 0).unwrap(), [8]);
+        assert_eq!(ti.next().unwrap(), 8);
+    }
+
+    #[test]
+    fn next_timeout_opt_duration_none_blocks() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter()).unwrap();
+
+        let sender = thread::spawn(move || {
+            thread::sleep(Duration::from_millis(100));
+            sink.send(1u32).unwrap();
+            sink
+        });
+
+        let started = Instant::now();
+        assert_eq!(ti.next_timeout_opt_duration(None).unwrap(), 1);
+        assert!(started.elapsed() >= Duration::from_millis(50));
+
+        let _sink = sender.join().unwrap();
+        assert_matches!(
+            ti.next_timeout_opt_duration(Some(Duration::from_millis(20))),
+            Err(error::Error::TimedOut)
+        );
+    }
+
+    #[test]
+    fn next_timeout_flat_propagates_both_errors() {
+        fn first_two(ti: &mut TimeoutIterator<std::io::Result<String>>) -> std::io::Result<String> {
+            let timeout = Duration::from_secs(1);
+            let first = ti.next_timeout_flat(timeout)?;
+            let second = ti.next_timeout_flat(timeout)?;
+            Ok(first + &second)
+        }
+
+        let lines = std::io::Cursor::new("one\ntwo\n").lines();
+        let mut ti = TimeoutIterator::with_iter(lines).unwrap();
+        assert_eq!(first_two(&mut ti).unwrap(), "onetwo");
+        assert_matches!(
+            ti.next_timeout_flat(Duration::from_secs(1)),
+            Err(error::FlatError::Iterator(error::Error::Disconnected))
+        );
+
+        let broken = vec![
+            Ok("one".to_owned()),
+            Err(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                "bad line",
+            )),
+        ];
+        let mut ti = TimeoutIterator::with_iter(broken.into_iter()).unwrap();
+        assert_eq!(
+            first_two(&mut ti).unwrap_err().kind(),
+            std::io::ErrorKind::InvalidData
+        );
+
+        let mut quiet = TimeoutIterator::with_iter(std::iter::from_fn(|| {
+            thread::park();
+            Some(Ok("never".to_owned()))
+        }))
+        .unwrap();
+        assert_eq!(
+            first_two(&mut quiet).unwrap_err().kind(),
+            std::io::ErrorKind::TimedOut
+        );
+    }
+
+    #[test]
+    fn next_if_consumes_only_matches() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter()).unwrap();
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(ti.next_if(timeout, |n| *n == 1).unwrap(), Some(1));
+        assert_eq!(ti.next_if(timeout, |n| *n == 1).unwrap(), None);
+        assert_eq!(ti.next().unwrap(), 2);
+    }
+
+    #[test]
+    fn next_if_times_out() {
+        let mut ti = TimeoutIterator::with_iter(silent()).unwrap();
+        assert_matches!(
+            ti.next_if(Duration::from_millis(20), |_| true),
+            Err(error::Error::TimedOut)
+        );
+    }
+
+    #[test]
+    fn next_if_eq_matches_tokens() {
+        let tokens = vec!["(", "x", ")"].into_iter();
+        let mut ti = TimeoutIterator::with_iter(tokens).unwrap();
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(ti.next_if_eq(timeout, &"(").unwrap(), Some("("));
+        assert_eq!(ti.next_if_eq(timeout, &")").unwrap(), None);
+        assert_eq!(*ti.buffer, ["x"]);
+    }
+
+    #[cfg(feature = "async")]
+    #[test]
+    fn into_stream_closed_resolves_at_end() {
+        let ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter()).unwrap();
+        let mut stream = ti.into_stream().unwrap();
+
+        futures::executor::block_on(stream.closed());
+        let items: Vec<u32> = futures::executor::block_on(stream.by_ref().collect());
+        assert_eq!(items, vec![1, 2]);
+
+        let endless = TimeoutIterator::with_iter(silent()).unwrap();
+        let stop = endless.stop_handle();
+        let stream = endless.into_stream().unwrap();
+        let closed = stream.closed();
+        stop.stop();
+        futures::executor::block_on(closed);
+    }
+
+    #[test]
+    fn tick_sets_condition_granularity() {
+        fn noticed_after(tick: Duration) -> Duration {
+            let clock = MockClock::auto_advancing();
+            let mut ti = TimeoutIterator::with_iter(silent())
+                .unwrap()
+                .with_clock(clock.clone());
+            ti.set_tick(tick);
+
+            let started = clock.now();
+            let flip_at = started + Duration::from_millis(20);
+            assert_matches!(
+                ti.next_timeout_or(Duration::from_secs(10), || clock.now() >= flip_at),
+                Err(error::Error::ConditionMet)
+            );
+            clock.now() - started
+        }
+
+        assert_eq!(
+            noticed_after(Duration::from_millis(400)),
+            Duration::from_millis(400)
+        );
+        assert_eq!(
+            noticed_after(Duration::from_millis(2)),
+            Duration::from_millis(20)
+        );
+    }
+
+    #[test]
+    fn tick_sets_heartbeat_granularity() {
+        fn heartbeat_after(tick: Duration) -> Duration {
+            let clock = MockClock::auto_advancing();
+            let started = clock.now();
+            let stamp = clock.clone();
+            let silent = std::iter::from_fn(|| loop {
+                thread::park();
+            });
+            let mut ti = TimeoutIterator::with_iter(silent)
+                .unwrap()
+                .with_clock(clock)
+                .with_heartbeat(Duration::from_millis(50), move || stamp.now() - started);
+            ti.set_tick(tick);
+            ti.next().unwrap()
+        }
+
+        assert_eq!(
+            heartbeat_after(Duration::from_millis(5)),
+            Duration::from_millis(50)
+        );
+        assert_eq!(
+            heartbeat_after(Duration::from_millis(30)),
+            Duration::from_millis(60)
+        );
+    }
+
+    #[test]
+    fn prepend_comes_first() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2].into_iter()).unwrap();
+        assert_eq!(*ti.peek().unwrap(), 1);
+
+        ti.prepend(vec![10, 20]);
+        assert_eq!(ti.collect::<Vec<_>>(), vec![10, 20, 1, 2]);
+    }
+
+    #[test]
+    fn into_blocking_yields_everything() {
+        fn total(items: impl Iterator<Item = u32>) -> u32 {
+            items.sum()
+        }
+
+        let mut ti = TimeoutIterator::with_iter(1u32..=4)
+            .unwrap()
+            .with_heartbeat(Duration::from_millis(1), || 100);
+        assert_eq!(*ti.peek().unwrap(), 1);
+        assert_eq!(total(ti.into_blocking()), 10);
+    }
+
+    #[test]
+    fn next_timeout_or_default_on_quiet_source() {
+        let timeout = Duration::from_millis(20);
+
+        let mut quiet = TimeoutIterator::with_iter(silent()).unwrap();
+        assert_eq!(quiet.next_timeout_or_default(timeout).unwrap(), 0);
+
+        let mut live = TimeoutIterator::with_iter(vec![5u32].into_iter()).unwrap();
+        assert_eq!(live.next_timeout_or_default(timeout).unwrap(), 5);
+        assert_matches!(
+            live.next_timeout_or_default(timeout),
+            Err(error::Error::Disconnected)
+        );
+    }
+
+    #[test]
+    fn adaptive_timeout_follows_cadence() {
+        let steady = (0u32..).inspect(|_| thread::sleep(Duration::from_millis(20)));
+        let mut ti = TimeoutIterator::with_iter(steady).unwrap();
+        let (floor, ceiling) = (Duration::from_millis(10), Duration::from_secs(1));
+        ti.enable_adaptive_timeout(2.0, floor, ceiling);
+        assert_eq!(ti.adaptive_timeout(), None);
+
+        for expected in 0..10 {
+            assert_eq!(ti.next_timeout(Duration::from_secs(10)).unwrap(), expected);
+        }
+        let timeout = ti.adaptive_timeout().unwrap();
+        assert!(timeout >= Duration::from_millis(30), "{:?}", timeout);
+        assert!(timeout <= Duration::from_millis(150), "{:?}", timeout);
+    }
+
+    #[test]
+    fn arrival_stats_of_throttled_source() {
+        let throttled = (0u32..10).inspect(|_| thread::sleep(Duration::from_millis(20)));
+        let mut ti = TimeoutIterator::with_iter(throttled).unwrap();
+        assert_eq!(ti.arrival_stats(), ArrivalStats::default());
+        // Every item was handed over, and so timed, before it could be consumed
+        assert_eq!(ti.by_ref().count(), 10);
+
+        let stats = ti.arrival_stats();
+        let (min, mean) = (stats.min.unwrap(), stats.mean.unwrap());
+        assert!(min >= Duration::from_millis(20), "{:?}", min);
+        assert!(min <= mean && mean <= stats.max.unwrap());
+    }
+
+    #[test]
+    fn peek_timeout_refresh_shows_latest() {
+        let (sink, source) = mpsc::channel();
+        let (done, enqueued) = mpsc::channel();
+        let mut ti =
+            TimeoutIterator::with_iter(source.into_iter().chain(signal_end(done))).unwrap();
+        let timeout = Duration::from_secs(1);
+
+        sink.send(1u32).unwrap();
+        assert_eq!(*ti.peek_timeout_refresh(timeout).unwrap(), 1);
+
+        sink.send(2).unwrap();
+        sink.send(3).unwrap();
+        drop(sink);
+        // Both are in the channel once the sender gets past them
+        enqueued.recv().unwrap();
+        assert_eq!(*ti.peek_timeout_refresh(timeout).unwrap(), 3);
+        assert_eq!(*ti.peek_timeout_refresh(timeout).unwrap(), 3);
+        assert_eq!(ti.next_timeout(timeout).unwrap(), 3);
+    }
+
+    #[test]
+    fn pause_and_resume_sender() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter()).unwrap();
+        let timeout = Duration::from_millis(50);
+
+        sink.send(1u32).unwrap();
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 1);
+
+        ti.pause();
+        sink.send(2).unwrap();
+        sink.send(3).unwrap();
+        // If the sender was already waiting on the source, one more item still gets through
+        let mut received = Vec::new();
+        while let Ok(item) = ti.next_timeout(timeout) {
+            received.push(item);
+        }
+        assert!(received.len() <= 1, "{:?}", received);
+
+        ti.resume();
+        while received.len() < 2 {
+            received.push(ti.next_timeout(Duration::from_secs(1)).unwrap());
+        }
+        assert_eq!(received, vec![2, 3]);
+    }
+
+    #[test]
+    fn dropping_paused_iterator_releases_sender() {
+        let ti = TimeoutIterator::with_iter(0u32..).unwrap();
+        let status = ti.sender_status();
+        ti.pause();
+        drop(ti);
+
+        let started = Instant::now();
+        while status.state() == SenderState::Running {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert_eq!(status.state(), SenderState::ReceiverDropped);
+    }
+
+    #[test]
+    fn with_iter_retry_survives_failed_spawns() {
+        FAILING_SPAWNS.with(|failing| failing.set(2));
+        let mut ti = TimeoutIterator::with_iter_retry(
+            vec![1u32, 2].into_iter(),
+            3,
+            Duration::from_millis(1),
+        )
+        .unwrap();
+        assert_eq!(ti.by_ref().collect::<Vec<_>>(), vec![1, 2]);
+
+        FAILING_SPAWNS.with(|failing| failing.set(2));
+        let failed =
+            TimeoutIterator::with_iter_retry(vec![1u32].into_iter(), 2, Duration::from_millis(1));
+        assert_matches!(failed.err(), Some(error::Error::ErrorSpawningThread(_)));
+        FAILING_SPAWNS.with(|failing| failing.set(0));
+    }
+
+    #[test]
+    fn reads_bytes() {
+        let mut ti = TimeoutIterator::with_iter(b"hello, world".to_vec().into_iter()).unwrap();
+
+        let mut first = [0u8; 5];
+        ti.read_exact(&mut first).unwrap();
+        assert_eq!(&first, b"hello");
+
+        let mut rest = Vec::new();
+        ti.read_to_end(&mut rest).unwrap();
+        assert_eq!(rest, b", world");
+        assert_eq!(ti.read(&mut first).unwrap(), 0);
+    }
+
+    #[test]
+    fn read_timeout_would_block() {
+        let bytes = std::iter::once(7u8).chain(silent().map(|_| 0));
+        let mut ti = TimeoutIterator::with_iter(bytes).unwrap();
+
+        let mut buf = [0u8; 4];
+        assert_eq!(
+            ti.read_timeout(&mut buf, Duration::from_secs(1)).unwrap(),
+            1
+        );
+        assert_eq!(buf[0], 7);
+        assert_eq!(
+            ti.read_timeout(&mut buf, Duration::from_millis(50))
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::WouldBlock
+        );
+    }
+
+    #[test]
+    fn read_line_timeout_keeps_partial_lines() {
+        let (chunks, source) = mpsc::channel::<&'static [u8]>();
+        let bytes = source.into_iter().flat_map(|chunk| chunk.iter().copied());
+        let mut ti = TimeoutIterator::with_iter(bytes).unwrap();
+
+        chunks.send(b"HELO exa").unwrap();
+        let mut line = String::new();
+        assert_eq!(
+            ti.read_line_timeout(&mut line, Duration::from_millis(100))
+                .unwrap_err()
+                .kind(),
+            std::io::ErrorKind::TimedOut
+        );
+        assert_eq!(line, "");
+
+        chunks.send(b"mple.com\r\nQUIT").unwrap();
+        drop(chunks);
+        assert_eq!(
+            ti.read_line_timeout(&mut line, Duration::from_secs(1))
+                .unwrap(),
+            18
+        );
+        assert_eq!(line, "HELO example.com\r\n");
+
+        line.clear();
+        assert_eq!(
+            ti.read_line_timeout(&mut line, Duration::from_secs(1))
+                .unwrap(),
+            4
+        );
+        assert_eq!(line, "QUIT");
+        assert_eq!(
+            ti.read_line_timeout(&mut line, Duration::from_secs(1))
+                .unwrap(),
+            0
+        );
+    }
+
+    #[test]
+    fn peek_two_compares_lookahead() {
+        let mut ti = TimeoutIterator::with_iter(vec![3, 3, 4].into_iter()).unwrap();
+
+        let (first, second) = ti.peek_two(Duration::from_secs(1)).unwrap();
+        assert_eq!(first, second);
+        assert_eq!(ti.next(), Some(3));
+        assert_eq!(ti.next(), Some(3));
+
+        assert_matches!(
+            ti.peek_two(Duration::from_secs(1)),
+            Err(error::Error::Disconnected)
+        );
+        assert_eq!(ti.next(), Some(4));
+    }
+
+    #[test]
+    fn merges_sources() {
+        let ti = merge(vec![
+            Box::new(vec![1, 2, 3].into_iter()),
+            Box::new(vec![10, 20].into_iter()),
+        ])
+        .unwrap();
+
+        let mut merged: Vec<u32> = ti.collect();
+        merged.sort_unstable();
+        assert_eq!(merged, vec![1, 2, 3, 10, 20]);
+    }
+
+    #[test]
+    fn merged_sources_finish_together() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = merge(vec![
+            Box::new(vec![1u32, 2].into_iter()),
+            Box::new(source.into_iter()),
+        ])
+        .unwrap()
+        .with_end_marker(|| 0);
+        let status = ti.sender_status();
+        let timeout = Duration::from_secs(1);
+
+        let mut first_two = vec![
+            ti.next_timeout(timeout).unwrap(),
+            ti.next_timeout(timeout).unwrap(),
+        ];
+        first_two.sort_unstable();
+        assert_eq!(first_two, vec![1, 2]);
+        assert_eq!(status.state(), SenderState::Running);
+
+        sink.send(3).unwrap();
+        drop(sink);
+        assert_eq!(ti.by_ref().collect::<Vec<_>>(), vec![3, 0]);
+        assert_eq!(status.state(), SenderState::Finished);
+    }
+
+    #[test]
+    fn empty_merge_is_finished() {
+        let mut ti = merge(Vec::<Box<dyn Iterator<Item = u32> + Send>>::new()).unwrap();
+        assert_eq!(ti.sender_status().state(), SenderState::Finished);
+        assert_matches!(
+            ti.next_timeout(Duration::from_secs(1)),
+            Err(error::Error::Disconnected)
+        );
+    }
+
+    #[test]
+    fn throttled_iter_spaces_items() {
+        let interval = Duration::from_millis(50);
+        let started = Instant::now();
+        let ti = TimeoutIterator::with_throttled_iter(0u32..5, interval).unwrap();
+
+        assert_eq!(ti.collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+        let elapsed = started.elapsed();
+        assert!(elapsed >= interval * 4, "{:?}", elapsed);
+    }
+
+    #[test]
+    fn wait_ready_then_drain() {
+        let slow = vec![1, 2, 3]
+            .into_iter()
+            .inspect(|_| thread::sleep(Duration::from_millis(20)));
+        let mut ti = TimeoutIterator::with_iter(slow).unwrap();
+
+        assert_matches!(
+            ti.wait_ready(Duration::from_millis(1)),
+            Err(error::Error::TimedOut)
+        );
+        let mut drained = Vec::new();
+        while ti.wait_ready(Duration::from_secs(1)).is_ok() {
+            drained.extend(ti.drain());
+        }
+        assert_eq!(drained, vec![1, 2, 3]);
+        assert_matches!(
+            ti.wait_ready(Duration::from_secs(1)),
+            Err(error::Error::Disconnected)
+        );
+    }
+
+    #[test]
+    fn next_array_timeout_takes_records() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3, 4, 5].into_iter()).unwrap();
+
+        let record: [u32; 3] = ti.next_array_timeout(Duration::from_secs(1)).unwrap();
+        assert_eq!(record, [1, 2, 3]);
+
+        assert_matches!(
+            ti.next_array_timeout::<3>(Duration::from_secs(1)),
+            Err(error::Error::Disconnected)
+        );
+        assert_eq!(ti.collect::<Vec<_>>(), vec![4, 5]);
+    }
+
+    #[test]
+    fn next_array_timeout_keeps_partial_record() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter()).unwrap();
+        sink.send(1u32).unwrap();
+
+        assert_matches!(
+            ti.next_array_timeout::<2>(Duration::from_millis(50)),
+            Err(error::Error::TimedOut)
+        );
+        sink.send(2).unwrap();
+        assert_eq!(
+            ti.next_array_timeout(Duration::from_secs(1)).unwrap(),
+            [1, 2]
+        );
+    }
+
+    #[test]
+    fn max_lookahead_caps_peek_while() {
+        let mut ti = TimeoutIterator::with_iter(0u32..100)
+            .unwrap()
+            .max_lookahead(8);
+
+        assert_matches!(
+            ti.peek_while(Duration::from_secs(1), |_| true),
+            Err(error::Error::LookaheadExceeded)
+        );
+        assert_matches!(
+            ti.fill_to(9, Duration::from_secs(1)),
+            Err(error::Error::LookaheadExceeded)
+        );
+        assert_eq!(
+            ti.peek_while(Duration::from_secs(1), |n| *n < 3).unwrap(),
+            [0, 1, 2]
+        );
+        assert_eq!(ti.next(), Some(0));
+    }
+
+    #[test]
+    fn state_handle_observes_from_another_thread() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32, 2, 3].into_iter()).unwrap();
+        let state = ti.state_handle();
+
+        ti.fill_to(3, Duration::from_secs(1)).unwrap();
+        assert_eq!(ti.next(), Some(1));
+
+        let observer = state.clone();
+        let seen = thread::spawn(move || (observer.buffered_len(), observer.items_received()));
+        assert_eq!(seen.join().unwrap(), (2, 3));
+        assert!(!state.is_disconnected());
+
+        assert_eq!(ti.by_ref().count(), 2);
+        assert_eq!(state.buffered_len(), 0);
+        assert!(state.is_disconnected());
+    }
+
+    #[test]
+    fn next_until_sentinel() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter()).unwrap();
+        let timeout = Duration::from_millis(50);
+
+        for item in [1u32, 2, 0, 3] {
+            sink.send(item).unwrap();
+        }
+        assert_eq!(ti.next_until(timeout, &0).unwrap(), vec![1, 2]);
+
+        // The frame so far is kept for the next try
+        assert_matches!(ti.next_until(timeout, &0), Err(error::Error::TimedOut));
+        sink.send(4).unwrap();
+        sink.send(0).unwrap();
+        assert_eq!(ti.next_until(timeout, &0).unwrap(), vec![3, 4]);
+
+        sink.send(5).unwrap();
+        drop(sink);
+        assert_matches!(ti.next_until(timeout, &0), Err(error::Error::Disconnected));
+        assert_eq!(ti.next(), Some(5));
+    }
+
+    #[test]
+    fn from_receiver_wraps_channel() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::from_receiver(source);
+        let timeout = Duration::from_millis(50);
+
+        sink.send(1u32).unwrap();
+        sink.send(2).unwrap();
+        assert_eq!(ti.next_timeout(timeout).unwrap(), 1);
+        assert_eq!(ti.next_timeout(timeout).unwrap(), 2);
+        assert_matches!(ti.next_timeout(timeout), Err(error::Error::TimedOut));
+
+        sink.send(3).unwrap();
+        drop(sink);
+        assert_eq!(ti.next_timeout(timeout).unwrap(), 3);
+        assert_matches!(ti.next_timeout(timeout), Err(error::Error::Disconnected));
+    }
+
+    #[test]
+    fn partitions_evens_and_odds() {
+        let ti = TimeoutIterator::with_iter(0u32..10).unwrap();
+        let (mut evens, odds) = ti.partition_timeout(|n| n % 2 == 0).unwrap();
+
+        // Draining one side first is fine
+        assert_eq!(odds.collect::<Vec<_>>(), vec![1, 3, 5, 7, 9]);
+        assert_eq!(evens.next_timeout(Duration::from_secs(1)).unwrap(), 0);
+        assert_eq!(evens.collect::<Vec<_>>(), vec![2, 4, 6, 8]);
+    }
+
+    #[test]
+    fn bounded_partition_holds_back_source() {
+        let (sink, source) = mpsc::channel();
+        let produced = Arc::new(AtomicUsize::new(0));
+        let counted = produced.clone();
+        let source = source.into_iter().inspect(move |_| {
+            counted.fetch_add(1, Ordering::SeqCst);
+        });
+
+        let ti = TimeoutIterator::with_iter(source).unwrap();
+        let status = ti.sender_status();
+        let capacity = 2;
+        let (mut evens, mut odds) = ti
+            .partition_bounded_timeout(|n| n % 2 == 0, capacity)
+            .unwrap();
+        // At most `capacity` items on the side, one with the router and one on its way there
+        let held_back = |consumed: usize| {
+            assert!(status.unconsumed.load(Ordering::SeqCst) <= 1);
+            let produced = produced.load(Ordering::SeqCst);
+            assert!(produced <= consumed + capacity + 2, "{}", produced);
+        };
+
+        // Nobody is consuming the evens yet
+        for n in 0u32..50 {
+            sink.send(n * 2).unwrap();
+            held_back(0);
+        }
+        sink.send(1).unwrap();
+        let timeout = Duration::from_secs(1);
+        for consumed in 0..50 {
+            held_back(consumed);
+            assert_eq!(evens.next_timeout(timeout).unwrap(), consumed as u32 * 2);
+        }
+        assert_eq!(odds.next_timeout(timeout).unwrap(), 1);
+
+        drop(sink);
+        assert_matches!(evens.next_timeout(timeout), Err(error::Error::Disconnected));
+        assert_matches!(odds.next_timeout(timeout), Err(error::Error::Disconnected));
+    }
+
+    #[test]
+    fn end_marker_comes_last() {
+        let mut ti = TimeoutIterator::with_iter(vec![Some(1u32), Some(2)].into_iter())
+            .unwrap()
+            .with_end_marker(|| None);
+        let timeout = Duration::from_secs(1);
+
+        assert_eq!(ti.next_timeout(timeout).unwrap(), Some(1));
+        assert_eq!(ti.next_timeout(timeout).unwrap(), Some(2));
+        assert_eq!(ti.next_timeout(timeout).unwrap(), None);
+        assert_matches!(ti.next_timeout(timeout), Err(error::Error::Disconnected));
+    }
+
+    #[test]
+    fn no_end_marker_after_panic() {
+        let panicking = (0u32..3).map(|n| if n == 2 { panic!("source failed") } else { n });
+        let ti = TimeoutIterator::with_iter(panicking)
+            .unwrap()
+            .with_end_marker(|| 99);
+
+        assert_eq!(ti.collect::<Vec<_>>(), vec![0, 1]);
+    }
+
+    #[test]
+    fn end_marker_after_replaced_source_and_fallback() {
+        let mut ti = TimeoutIterator::with_iter(vec![1u32].into_iter())
+            .unwrap()
+            .with_end_marker(|| 0)
+            .chain_on_disconnect(vec![3u32].into_iter());
+        ti.replace_source(vec![2u32].into_iter()).unwrap();
+
+        assert_eq!(ti.collect::<Vec<_>>(), vec![2, 3, 0]);
+    }
+
+    #[test]
+    fn end_marker_of_receiver() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::from_receiver(source).with_end_marker(|| 0u32);
+        sink.send(1).unwrap();
+        drop(sink);
+
+        assert_eq!(ti.next(), Some(1));
+        assert_eq!(ti.next(), Some(0));
+        assert_eq!(ti.next(), None);
+    }
+
+    #[test]
+    fn end_marker_counts_towards_upper_bound() {
+        let mut ti = TimeoutIterator::with_exact_iter(vec![1u32, 2].into_iter())
+            .unwrap()
+            .with_end_marker(|| 0);
+        assert_eq!(ti.size_hint(), (2, Some(3)));
+        assert_eq!(ti.remaining_hint(), Some(2));
+
+        assert_eq!(ti.next(), Some(1));
+        assert_eq!(ti.next(), Some(2));
+        assert_eq!(ti.size_hint(), (0, Some(1)));
+        assert_eq!(ti.remaining_hint(), Some(0));
+        assert_eq!(ti.next(), Some(0));
+        assert_eq!(ti.size_hint(), (0, Some(0)));
+        assert_eq!(ti.next(), None);
+    }
+
+    #[test]
+    fn reset_stats_starts_over() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter()).unwrap();
+        let state = ti.state_handle();
+        let timeout = Duration::from_secs(1);
+        assert_eq!(ti.arrival_stats(), ArrivalStats::default());
+
+        for n in 0u32..3 {
+            sink.send(n).unwrap();
+            assert_eq!(ti.next_timeout(timeout).unwrap(), n);
+        }
+        assert!(ti.arrival_stats().mean.is_some());
+        assert_eq!(state.items_received(), 3);
+
+        ti.reset_stats();
+        assert_eq!(ti.arrival_stats(), ArrivalStats::default());
+        assert_eq!(state.items_received(), 0);
+
+        sink.send(3).unwrap();
+        assert_eq!(ti.next_timeout(timeout).unwrap(), 3);
+        assert_eq!(state.items_received(), 1);
+        assert!(ti.arrival_stats().mean.is_some());
+    }
+
+    #[test]
+    fn arrivals_tracked_once_asked_for() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::with_iter(source.into_iter()).unwrap();
+        let timeout = Duration::from_secs(1);
+
+        for n in 0u32..3 {
+            sink.send(n).unwrap();
+            assert_eq!(ti.next_timeout(timeout).unwrap(), n);
+        }
+        assert_eq!(ti.arrival_stats(), ArrivalStats::default());
+
+        for n in 3u32..5 {
+            sink.send(n).unwrap();
+            assert_eq!(ti.next_timeout(timeout).unwrap(), n);
+        }
+        assert!(ti.arrival_stats().mean.is_some());
+
+        // Still tracked for a swapped-in source
+        ti.replace_source(vec![5u32, 6].into_iter()).unwrap();
+        assert_eq!(ti.by_ref().count(), 2);
+        assert!(ti.arrival_stats().mean.is_some());
+    }
+
+    #[test]
+    fn no_arrival_stats_from_receiver() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::from_receiver(source);
+        assert_eq!(ti.arrival_stats(), ArrivalStats::default());
+
+        for n in 0u32..3 {
+            sink.send(n).unwrap();
+        }
+        drop(sink);
+        assert_eq!(ti.by_ref().count(), 3);
+        assert_eq!(ti.arrival_stats(), ArrivalStats::default());
+    }
+
+    #[test]
+    fn cloned_snapshot_owns_items() {
+        let names = ["a".to_owned(), "b".to_owned()];
+        let mut ti = TimeoutIterator::from_cloned_snapshot(names.iter());
+        let timeout = Duration::from_millis(50);
+
+        assert_eq!(ti.next_timeout(timeout).unwrap(), "a");
+        assert_eq!(ti.next_timeout(timeout).unwrap(), "b");
+        assert_matches!(ti.next_timeout(timeout), Err(error::Error::Disconnected));
+        assert_eq!(names.len(), 2);
+    }
+
+    #[test]
+    fn cloned_snapshot_of_slow_source_is_taken_up_front() {
+        let numbers = [1u32, 2, 3];
+        let cloned = Arc::new(AtomicUsize::new(0));
+        let counter = cloned.clone();
+        let slow = numbers.iter().inspect(move |_| {
+            thread::sleep(Duration::from_millis(10));
+            counter.fetch_add(1, Ordering::AcqRel);
+        });
+
+        let mut ti = TimeoutIterator::from_cloned_snapshot(slow);
+        assert_eq!(cloned.load(Ordering::Acquire), 3);
+        for expected in 1..=3 {
+            assert_eq!(ti.next_timeout(Duration::ZERO).unwrap(), expected);
+        }
+        assert_matches!(
+            ti.next_timeout(Duration::ZERO),
+            Err(error::Error::Disconnected)
+        );
+    }
+
+    #[test]
+    fn prepend_keeps_exact_size() {
+        let mut ti = TimeoutIterator::with_exact_iter(vec![1u32, 2].into_iter()).unwrap();
+        ti.prepend(vec![10, 20]);
+        assert_eq!(ti.size_hint(), (4, Some(4)));
+
+        assert_eq!(ti.next(), Some(10));
+        assert_eq!(ti.size_hint(), (3, Some(3)));
+        assert_eq!(ti.collect::<Vec<_>>(), vec![20, 1, 2]);
+    }
+
+    #[test]
+    fn prepended_items_count_as_unconsumed() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = Builder::new()
+            .max_buffered(2)
+            .with_iter(source.into_iter())
+            .unwrap();
+
+        sink.send(1u32).unwrap();
+        assert_eq!(ti.next_timeout(Duration::from_secs(1)).unwrap(), 1);
+        ti.prepend(vec![10]);
+        assert_eq!(ti.next(), Some(10));
+        assert_eq!(ti.sender_status().unconsumed.load(Ordering::Acquire), 0);
+    }
+
+    #[test]
+    fn adaptive_timeout_ignores_queued_items() {
+        let (sink, source) = mpsc::channel();
+        let mut ti = TimeoutIterator::from_receiver(source);
+        let (floor, ceiling) = (Duration::from_millis(1), Duration::from_secs(10));
+        ti.enable_adaptive_timeout(1.0, floor, ceiling);
+
+        for i in 0u32..5 {
+            sink.send(i).unwrap();
+        }
+        for expected in 0..5 {
+            assert_eq!(ti.next_timeout(Duration::from_secs(10)).unwrap(), expected);
+        }
+        assert_eq!(ti.adaptive_timeout(), None);
+
+        let producer = thread::spawn(move || {
+            for i in 5..8 {
+                thread::sleep(Duration::from_millis(50));
+                sink.send(i).unwrap();
+            }
+        });
+        for expected in 5..8 {
+            assert_eq!(ti.next_timeout(Duration::from_secs(10)).unwrap(), expected);
+        }
+        producer.join().unwrap();
+
+        let timeout = ti.adaptive_timeout().unwrap();
+        assert!(timeout >= Duration::from_millis(25), "{:?}", timeout);
+    }
+
+    #[test]
+    fn adaptive_timeout_rejects_bad_settings() {
+        let (floor, ceiling) = (Duration::from_millis(10), Duration::from_secs(1));
+        let settings = [
+            (0.0, floor, ceiling),
+            (-1.0, floor, ceiling),
+            (f64::NAN, floor, ceiling),
+            (f64::INFINITY, floor, ceiling),
+            (2.0, ceiling, floor),
+        ];
+        for (factor, floor, ceiling) in settings {
+            let enabled = std::panic::catch_unwind(move || {
+                let mut ti = TimeoutIterator::from_buffered(vec![1u32]);
+                ti.enable_adaptive_timeout(factor, floor, ceiling);
+            });
+            assert!(enabled.is_err(), "{} {:?} {:?}", factor, floor, ceiling);
+        }
     }
 }
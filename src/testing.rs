@@ -0,0 +1,88 @@
+use crate::synchronous::Clock;
+use std::sync::mpsc;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::{Duration, Instant};
+
+/// How long a waiting `MockClock::recv_timeout` goes between checks of the channel.
+///
+/// Items arriving on the channel can't wake the clock, so waiters look again this often,
+/// in real time, while they wait for the clock to be advanced.
+const POLL_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A manually driven `Clock` for deterministic tests.
+///
+/// Time only moves when `advance` is called. Clones share the same time, so one clone can
+/// be handed to a `TimeoutIterator` while another advances it from the test.
+#[derive(Clone, Debug)]
+pub struct MockClock {
+    state: Arc<(Mutex<Instant>, Condvar)>,
+    auto_advance: bool,
+}
+
+impl MockClock {
+    pub fn new() -> MockClock {
+        MockClock {
+            state: Arc::new((Mutex::new(Instant::now()), Condvar::new())),
+            auto_advance: false,
+        }
+    }
+
+    /// A clock that, rather than waiting to be advanced, jumps straight to the deadline of
+    /// any wait that finds the channel empty, so a single-threaded test can step through a
+    /// quiet source's timeouts. An item that is still on its way to the channel counts as
+    /// not having arrived, so this suits sources that are either ready or silent.
+    pub fn auto_advancing() -> MockClock {
+        MockClock {
+            auto_advance: true,
+            ..MockClock::new()
+        }
+    }
+
+    /// Moves time forward, waking anyone waiting on a timeout that has now expired.
+    pub fn advance(&self, duration: Duration) {
+        let (now, advanced) = &*self.state;
+        *now.lock().unwrap() += duration;
+        advanced.notify_all();
+    }
+}
+
+impl Default for MockClock {
+    fn default() -> MockClock {
+        MockClock::new()
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Instant {
+        *self.state.0.lock().unwrap()
+    }
+
+    fn recv_timeout<T>(
+        &self,
+        source: &mpsc::Receiver<T>,
+        timeout: Duration,
+    ) -> Result<T, mpsc::RecvTimeoutError> {
+        let (now, advanced) = &*self.state;
+        let deadline = self.now() + timeout;
+
+        loop {
+            match source.try_recv() {
+                Ok(item) => return Ok(item),
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    return Err(mpsc::RecvTimeoutError::Disconnected)
+                }
+                Err(mpsc::TryRecvError::Empty) => {}
+            }
+
+            let mut current = now.lock().unwrap();
+            if self.auto_advance && *current < deadline {
+                *current = deadline;
+                advanced.notify_all();
+            }
+            if *current >= deadline {
+                return Err(mpsc::RecvTimeoutError::Timeout);
+            }
+            let _ = advanced.wait_timeout(current, POLL_INTERVAL).unwrap();
+        }
+    }
+}